@@ -0,0 +1,108 @@
+//! CPU throughput of the encoder, one sequence per forward pass vs padded batches.
+//!
+//! Weights are random but shaped like all-MiniLM-L6-v2, so this runs without the `hub/`
+//! cache and measures the same amount of work as the real model.
+//!
+//! `cargo bench --bench embeddings`, set `RAYON_NUM_THREADS` to compare thread counts
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::bert::HiddenAct;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pr_dedupe::encoder::{mean_pool, Encoder, EncoderConfig};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// sequences embedded per iteration
+const SEQUENCES: usize = 32;
+/// all-MiniLM-L6-v2's tokenizer truncates at 128 tokens
+const MAX_TOKENS: usize = 128;
+
+fn minilm_shaped_encoder(device: &Device) -> Encoder {
+    let config = EncoderConfig {
+        vocab_size: 30522,
+        hidden_size: 384,
+        num_hidden_layers: 6,
+        num_attention_heads: 12,
+        intermediate_size: 1536,
+        hidden_act: HiddenAct::Gelu,
+        max_position_embeddings: 512,
+        type_vocab_size: 2,
+        layer_norm_eps: 1e-12,
+        model_type: None,
+    };
+    let varmap = VarMap::new();
+    Encoder::load(
+        VarBuilder::from_varmap(&varmap, DType::F32, device),
+        &config,
+    )
+    .unwrap()
+}
+
+/// Token ids of sequences between 16 and `MAX_TOKENS` long, like PRs of different sizes
+fn sequences() -> Vec<Vec<u32>> {
+    let mut rng = StdRng::seed_from_u64(7);
+    (0..SEQUENCES)
+        .map(|_| {
+            let len = rng.gen_range(16..=MAX_TOKENS);
+            (0..len).map(|_| rng.gen_range(1000..30000)).collect()
+        })
+        .collect()
+}
+
+/// Pads the sequences to the longest one and embeds them in one forward pass
+fn embed(encoder: &Encoder, sequences: &[Vec<u32>], device: &Device) -> Tensor {
+    let seq_len = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let (mut ids, mut mask) = (vec![], vec![]);
+    for sequence in sequences {
+        let padding = seq_len - sequence.len();
+        ids.extend(
+            sequence
+                .iter()
+                .copied()
+                .chain(std::iter::repeat(0).take(padding)),
+        );
+        mask.extend(
+            std::iter::repeat(1u32)
+                .take(sequence.len())
+                .chain(std::iter::repeat(0).take(padding)),
+        );
+    }
+    let shape = (sequences.len(), seq_len);
+    let ids = Tensor::from_vec(ids, shape, device).unwrap();
+    let mask = Tensor::from_vec(mask, shape, device).unwrap();
+
+    let hidden = encoder
+        .forward(&ids, &ids.zeros_like().unwrap(), &mask)
+        .unwrap();
+    mean_pool(&hidden, &mask).unwrap()
+}
+
+fn throughput(c: &mut Criterion) {
+    let device = Device::Cpu;
+    let encoder = minilm_shaped_encoder(&device);
+    let mut sequences = sequences();
+    // what `Bert::embed_batch` does to keep padding short
+    sequences.sort_by_key(Vec::len);
+
+    let mut group = c.benchmark_group("encoder");
+    group.throughput(Throughput::Elements(SEQUENCES as u64));
+    group.sample_size(10);
+
+    for batch_size in [1, 8, 32] {
+        group.bench_with_input(
+            BenchmarkId::new("batch_size", batch_size),
+            &batch_size,
+            |b, &batch_size| {
+                b.iter(|| {
+                    for batch in sequences.chunks(batch_size) {
+                        embed(&encoder, batch, &device);
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,201 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use futures::stream::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bert::Embedder,
+    content::build_pr_content,
+    error::{self, Error},
+    github::{GitHub, PullRequest},
+    http::HttpClient,
+    id::{ItemId, ItemKind},
+    utils::{EmbeddedItem, VectorDB},
+};
+
+/// PRs that were already upserted, per repo. Written after every batch so an interrupted
+/// backfill can pick up where it stopped
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct BackfillState {
+    indexed: HashMap<String, BTreeSet<u64>>,
+}
+
+impl BackfillState {
+    fn load(path: &Path) -> Result<Self> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+pub struct Backfill<'a, DB: VectorDB> {
+    pub repo_name: &'a str,
+    /// also index PRs closed on or after this date (`YYYY-MM-DD`)
+    pub closed_since: Option<&'a str>,
+    pub batch_size: usize,
+    pub state_file: &'a Path,
+    pub embedder: &'a Embedder,
+    pub vector_db: &'a DB,
+    pub http: &'a Arc<HttpClient>,
+}
+
+impl<DB: VectorDB> Backfill<'_, DB> {
+    pub async fn run(&self) -> error::Result<()> {
+        let github = GitHub::new(self.http.clone()).map_err(Error::Config)?;
+
+        let mut state = BackfillState::load(self.state_file).map_err(|e| {
+            Error::Config(anyhow!(
+                "Couldn't read backfill state from {} | Reason {e}",
+                self.state_file.display()
+            ))
+        })?;
+        let indexed = state.indexed.entry(self.repo_name.to_string()).or_default();
+
+        let pulls = self
+            .list_pulls(&github)
+            .await
+            .map_err(Error::Network)?
+            .into_iter()
+            .filter(|pr| !indexed.contains(&pr.number))
+            .collect::<Vec<_>>();
+
+        if !indexed.is_empty() {
+            info!(
+                "resuming backfill, skipping {} already indexed PRs",
+                indexed.len()
+            );
+        }
+        info!("backfilling {} PRs from {}", pulls.len(), self.repo_name);
+
+        let version = self.embedder.version();
+        let mut done = 0;
+
+        for batch in pulls.chunks(self.batch_size.max(1)) {
+            let embeddings =
+                embed_pulls(&github, self.http, self.embedder, self.repo_name, batch).await?;
+
+            self.vector_db
+                .save_embeddings(&embeddings, &version)
+                .await
+                .map_err(Error::Backend)?;
+
+            // PRs left out of the batch are retried on the next run
+            state
+                .indexed
+                .entry(self.repo_name.to_string())
+                .or_default()
+                .extend(embeddings.iter().map(|e| e.id.number));
+            state.save(self.state_file).map_err(|e| {
+                Error::Config(anyhow!(
+                    "Couldn't save backfill state to {} | Reason {e}",
+                    self.state_file.display()
+                ))
+            })?;
+
+            done += embeddings.len();
+            info!("indexed {done}/{} PRs", pulls.len());
+        }
+
+        info!("finished backfilling {}", self.repo_name);
+        Ok(())
+    }
+
+    /// Pages through the repo's open PRs and, if requested, the ones closed since `closed_since`
+    async fn list_pulls(&self, github: &GitHub) -> Result<Vec<PullRequest>> {
+        let mut pulls = Vec::new();
+
+        for page in 1.. {
+            let page_pulls = github.list_pulls(self.repo_name, "open", page).await?;
+            if page_pulls.is_empty() {
+                break;
+            }
+            pulls.extend(page_pulls);
+        }
+
+        let Some(closed_since) = self.closed_since else {
+            return Ok(pulls);
+        };
+
+        // PRs are sorted by last update and a PR can't be updated before it's closed, so
+        // paging can stop at the first PR that hasn't been touched since `closed_since`
+        'pages: for page in 1.. {
+            let page_pulls = github.list_pulls(self.repo_name, "closed", page).await?;
+            if page_pulls.is_empty() {
+                break;
+            }
+            for pr in page_pulls {
+                if pr.updated_at.as_str() < closed_since {
+                    break 'pages;
+                }
+                match &pr.closed_at {
+                    Some(closed_at) if closed_at.as_str() >= closed_since => pulls.push(pr),
+                    Some(_) => {}
+                    None => warn!("closed PR #{} has no closed_at date", pr.number),
+                }
+            }
+        }
+
+        Ok(pulls)
+    }
+}
+
+/// Builds each PR's content the same way the action does, then embeds and fingerprints it.
+/// PRs whose content can't be built, e.g. touching a file that isn't UTF-8, are logged and
+/// left out so they don't hold back the rest of the batch
+pub async fn embed_pulls(
+    github: &GitHub,
+    downloads: &HttpClient,
+    embedder: &Embedder,
+    repo_name: &str,
+    pulls: &[PullRequest],
+) -> error::Result<Vec<EmbeddedItem>> {
+    let contents = futures::stream::iter(pulls.iter().map(|pr| async {
+        let id = ItemId::new(github.host(), repo_name, ItemKind::Pull, pr.number)?;
+        let files = github.pull_files(repo_name, pr.number).await?;
+        let content =
+            build_pr_content(downloads, &id, &pr.head.sha, Some(&pr.base.sha), &files).await?;
+        Ok::<_, anyhow::Error>((id, content))
+    }))
+    .buffered(4)
+    .collect::<Vec<_>>()
+    .await
+    .into_iter()
+    .zip(pulls)
+    .filter_map(|(content, pr)| match content {
+        Ok(content) => Some(content),
+        Err(e) => {
+            warn!("skipping {repo_name}#{} | Reason {e}", pr.number);
+            None
+        }
+    })
+    .collect::<Vec<_>>();
+
+    let embeddings = embedder
+        .embed_batch(
+            &contents
+                .iter()
+                .map(|(_, content)| content.clone())
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .map_err(Error::Model)?;
+
+    Ok(contents
+        .into_iter()
+        .zip(embeddings)
+        .map(|((id, content), embedding)| EmbeddedItem::new(id, &content, embedding))
+        .collect())
+}
@@ -1,20 +1,61 @@
 // Derived from https://github.com/scrippt-tech/orca
 
 use anyhow::{anyhow, Error as E, Result};
-use candle_core::{Device, Tensor};
+use candle_core::{quantized::GgmlDType, Device, Tensor};
 use candle_nn::VarBuilder;
-use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use candle_transformers::models::bert::DTYPE;
+use clap::ValueEnum;
 use hf_hub::{Cache, Repo, RepoType};
 
 use log::info;
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeSet, HashMap},
     fmt::Display,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::Arc,
 };
-use tokenizers::{PaddingParams, Tokenizer};
-use tokio::sync::RwLock;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer};
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::{
+    embedding_cache::EmbeddingCache,
+    encoder::{mean_pool, Encoder, EncoderConfig},
+    file_weights::FileWeights,
+    fingerprint::normalize,
+};
+
+/// Bumped whenever content building, chunking or pooling changes in a way that makes new
+/// vectors incomparable with the ones already stored
+pub const PIPELINE_VERSION: u32 = 4;
+
+/// max chars of a chunk, about as much code as the 128 tokens all-MiniLM-L6-v2 reads
+const CHUNK_CHARS: usize = 512;
+/// chunks embedded per file, the rest of very large files is left out
+const MAX_CHUNKS: usize = 16;
+
+/// Identifies what produced a vector. Vectors are only comparable when all fields match.
+///
+/// [`Precision`] is deliberately left out: quantized vectors stay within
+/// [`Precision::TOLERANCE`] of F32 ones, which `check-precision` verifies per model, so an
+/// index can mix both and switching precision doesn't need a reindex
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddingVersion {
+    pub model_id: String,
+    pub revision: String,
+    pub pipeline_version: u32,
+}
 
+/// ```no_run
+/// use pr_dedupe::{Bert, Embedding};
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let bert = Bert::new().build_model_and_tokenizer().await?;
+/// let embedding = bert.generate_embeddings(vec!["fix typo in README"]).await?.to_vec()?;
+/// assert_eq!(embedding.len(), 384);
+/// # Ok(())
+/// # }
+/// ```
 #[async_trait::async_trait]
 pub trait Embedding {
     async fn generate_embeddings(&self, prompts: Vec<&str>) -> Result<EmbeddingResponse>;
@@ -74,19 +115,70 @@ impl Display for EmbeddingResponse {
     }
 }
 
+/// Encoder architectures [`Bert`] can load, from the model's `config.json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    Bert,
+    /// RoBERTa encoders, e.g. CodeBERT and most code embedders. Same layers as BERT, but
+    /// positions start after the padding token and there's a single token type
+    Roberta,
+}
+
+impl Architecture {
+    fn from_config(config: &serde_json::Value) -> Result<Self> {
+        match config["model_type"].as_str().unwrap_or("bert") {
+            "bert" => Ok(Architecture::Bert),
+            "roberta" => Ok(Architecture::Roberta),
+            other => Err(anyhow!(
+                "Unsupported model type {other}, only bert and roberta encoders are supported"
+            )),
+        }
+    }
+}
+
+/// Weights inference runs with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Precision {
+    /// the weights as published
+    #[default]
+    F32,
+    /// linear layers quantized to 8 bits when loaded, faster on CPU. Its vectors stay
+    /// comparable with F32 ones, see [`Precision::TOLERANCE`]
+    Int8,
+}
+
+impl Precision {
+    /// How far below 1 the cosine similarity of a quantized vector and its F32 counterpart
+    /// may drop, checked by `check-precision`
+    pub const TOLERANCE: f32 = 0.01;
+
+    fn ggml_dtype(self) -> Option<GgmlDType> {
+        match self {
+            Precision::F32 => None,
+            Precision::Int8 => Some(GgmlDType::Q8_0),
+        }
+    }
+}
+
 pub struct Bert {
     /// The model to use, check out available models: https://huggingface.co/models?library=sentence-transformers&sort=trending
     model_id: Option<String>,
 
     /// Model weights.
-    model: Option<Arc<BertModel>>,
+    model: Option<Arc<Encoder>>,
 
     /// Tokenizer.
     tokenizer: Option<RwLock<Tokenizer>>,
 
     revision: Option<String>,
 
+    /// prompts per forward pass, see [`Bert::embed_batch`]
+    batch_size: usize,
+
+    precision: Precision,
+
     /// L2 normalization for embeddings.
+    #[allow(dead_code)]
     normalize_embeddings: bool,
 }
 
@@ -94,10 +186,12 @@ impl Default for Bert {
     /// Provides default values for `Bert`.
     fn default() -> Self {
         Self {
-            model_id: Some("sentence-transformers/all-MiniLM-L6-v2".to_string()),
+            model_id: Some(Self::MODEL_ID.to_string()),
             model: None,
             tokenizer: None,
-            revision: Some("refs/pr/21".to_string()),
+            revision: Some(Self::REVISION.to_string()),
+            batch_size: Self::BATCH_SIZE,
+            precision: Precision::default(),
             normalize_embeddings: false,
         }
     }
@@ -110,10 +204,48 @@ impl AsRef<Self> for Bert {
 }
 
 impl Bert {
+    /// default model, trained on English sentences with an uncased vocab
+    pub const MODEL_ID: &'static str = "sentence-transformers/all-MiniLM-L6-v2";
+    pub const REVISION: &'static str = "refs/pr/21";
+    /// RoBERTa model trained on code search, which keeps code tokens' case and punctuation.
+    /// Its vectors have 768 dimensions instead of 384, so it needs its own vector db index
+    pub const CODE_MODEL_ID: &'static str =
+        "flax-sentence-embeddings/st-codesearch-distilroberta-base";
+    pub const CODE_REVISION: &'static str = "main";
+    /// prompts per forward pass, larger batches are faster until padding dominates
+    pub const BATCH_SIZE: usize = 8;
+
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Uses another BERT or RoBERTa model from the `hub/` cache, e.g. [`Bert::CODE_MODEL_ID`]
+    /// for repos that are mostly code
+    pub fn with_model(mut self, model_id: &str, revision: &str) -> Self {
+        self.model_id = Some(model_id.to_string());
+        self.revision = Some(revision.to_string());
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// The version stored alongside every vector this model produces
+    pub fn version(&self) -> EmbeddingVersion {
+        EmbeddingVersion {
+            model_id: self.model_id.clone().unwrap_or_default(),
+            revision: self.revision.clone().unwrap_or_default(),
+            pipeline_version: PIPELINE_VERSION,
+        }
+    }
+
     pub fn device() -> Device {
         // default to CPU, Metal randomly errors out ('Wouldblock')
         // TODO: reach out to the huggingface/candle team + find out why
@@ -130,37 +262,80 @@ impl Bert {
         // }
     }
 
+    /// The config, tokenizer and weights files of a model revision in the `hub/` cache
+    pub(crate) fn cached_files(
+        model_id: &str,
+        revision: &str,
+    ) -> Result<(PathBuf, PathBuf, PathBuf)> {
+        let repo = Repo::with_revision(model_id.to_string(), RepoType::Model, revision.to_string());
+        let cache = Cache::default().repo(repo);
+        Ok((
+            cache.get("config.json").ok_or(anyhow!(
+                "Missing {model_id} config file in cache, see `model verify`"
+            ))?,
+            cache.get("tokenizer.json").ok_or(anyhow!(
+                "Missing {model_id} tokenizer file in cache, see `model verify`"
+            ))?,
+            cache.get("model.safetensors").ok_or(anyhow!(
+                "Missing {model_id} weights file in cache, see `model verify`"
+            ))?,
+        ))
+    }
+
     /// Builds the model and tokenizer.
+    /// Maps the weights for [`Encoder::load`], adjusting RoBERTa's position embeddings and
+    /// `config` to match
+    pub(crate) fn var_builder(
+        config: &mut serde_json::Value,
+        weights_filename: &Path,
+        device: &Device,
+    ) -> Result<VarBuilder<'static>> {
+        Ok(match Architecture::from_config(config)? {
+            Architecture::Bert => unsafe {
+                VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, device)?
+            },
+            Architecture::Roberta => {
+                // the encoder numbers positions from 0, RoBERTa from `pad_token_id + 1`,
+                // so the first rows of the position embeddings are dropped instead
+                let offset = config["pad_token_id"].as_u64().unwrap_or(1) as usize + 1;
+                let mut tensors = candle_core::safetensors::load(weights_filename, device)?;
+                for (_, positions) in tensors
+                    .iter_mut()
+                    .filter(|(name, _)| name.ends_with("embeddings.position_embeddings.weight"))
+                {
+                    *positions = positions.narrow(0, offset, positions.dim(0)? - offset)?;
+                }
+                if let Some(max_positions) = config["max_position_embeddings"].as_u64() {
+                    config["max_position_embeddings"] = (max_positions - offset as u64).into();
+                }
+                VarBuilder::from_tensors(tensors, DTYPE, device)
+            }
+        })
+    }
+
     pub async fn build_model_and_tokenizer(mut self) -> Result<Self> {
         let device = Self::device();
 
-        let repo = Repo::with_revision(
-            self.model_id.clone().unwrap(),
-            RepoType::Model,
-            self.revision.clone().unwrap(),
-        );
-        //
-        let (config_filename, tokenizer_filename, weights_filename) = {
-            let cache = Cache::default().repo(repo);
-            (
-                cache
-                    .get("config.json")
-                    .ok_or(anyhow!("Missing config file in cache"))?,
-                cache
-                    .get("tokenizer.json")
-                    .ok_or(anyhow!("Missing tokenizer file in cache"))?,
-                cache
-                    .get("model.safetensors")
-                    .ok_or(anyhow!("Missing weights file in cache"))?,
-            )
-        };
+        let model_id = self.model_id.clone().unwrap();
+        let (config_filename, tokenizer_filename, weights_filename) =
+            Self::cached_files(&model_id, self.revision.as_deref().unwrap())?;
         let config = std::fs::read_to_string(config_filename)?;
-        let config: Config = serde_json::from_str(&config)?;
-        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
-
-        let vb =
-            unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? };
-        let model = BertModel::load(vb, &config)?;
+        let mut config: serde_json::Value = serde_json::from_str(&config)?;
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        // tokenizer.json may pad to a fixed length, batches only need to match their longest
+        let padding = PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..tokenizer.get_padding().cloned().unwrap_or_default()
+        };
+        tokenizer.with_padding(Some(padding));
+
+        let vb = Self::var_builder(&mut config, &weights_filename, &device)?;
+        let config: EncoderConfig = serde_json::from_value(config)?;
+        let mut model = Encoder::load(vb, &config)?;
+        if let Some(dtype) = self.precision.ggml_dtype() {
+            model = model.quantize(dtype)?;
+            info!("quantized {model_id} to {dtype:?}");
+        }
         self.model = Some(Arc::new(model));
         self.tokenizer = Some(RwLock::new(tokenizer));
         Ok(self)
@@ -169,103 +344,299 @@ impl Bert {
 
 #[async_trait::async_trait]
 impl Embedding for Bert {
+    /// Hidden states of every prompt's tokens in one forward pass, padded to the longest
+    /// prompt. See [`Bert::embed_batch`] for pooled vectors
     async fn generate_embeddings(&self, prompts: Vec<&str>) -> Result<EmbeddingResponse> {
-        if self.model.is_none() || self.tokenizer.is_none() {
+        let (hidden, _mask) = self.forward_batch(prompts).await?;
+        Ok(EmbeddingResponse::Bert(hidden))
+    }
+}
+
+impl Bert {
+    /// Runs a padded batch through the model, returning the hidden states and attention mask
+    async fn forward_batch(&self, prompts: Vec<&str>) -> Result<(Tensor, Tensor)> {
+        let (Some(model), Some(tokenizer)) = (&self.model, &self.tokenizer) else {
             return Err(anyhow!("Model or tokenizer not initialized"));
+        };
+        let device = &model.device;
+
+        let encodings = tokenizer
+            .read()
+            .await
+            .encode_batch(prompts, true)
+            .map_err(E::msg)?;
+        let seq_len = encodings.first().map_or(0, |e| e.len());
+        let batch = |ids: fn(&tokenizers::Encoding) -> &[u32]| {
+            let ids = encodings
+                .iter()
+                .flat_map(|e| ids(e).to_vec())
+                .collect::<Vec<_>>();
+            Tensor::from_vec(ids, (encodings.len(), seq_len), device)
+        };
+        let token_ids = batch(|e| e.get_ids())?;
+        let token_type_ids = batch(|e| e.get_type_ids())?;
+        let attention_mask = batch(|e| e.get_attention_mask())?;
+
+        let hidden = model.forward(&token_ids, &token_type_ids, &attention_mask)?;
+        Ok((hidden, attention_mask))
+    }
+
+    /// Embeds every prompt, mean pooling its tokens' hidden states. Prompts are run
+    /// `batch_size` at a time, grouped by length to keep padding short, and padding is masked
+    /// so each vector is the same as embedding its prompt on its own
+    pub async fn embed_batch(&self, prompts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let start = std::time::Instant::now();
+
+        let mut order = (0..prompts.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| prompts[i].len());
+
+        let mut embeddings = vec![vec![]; prompts.len()];
+        for batch in order.chunks(self.batch_size.max(1)) {
+            let batch_prompts = batch.iter().map(|&i| prompts[i].as_str()).collect();
+            let (hidden, mask) = self.forward_batch(batch_prompts).await?;
+            let pooled = mean_pool(&hidden, &mask)?.to_vec2::<f32>()?;
+            for (&i, embedding) in batch.iter().zip(pooled) {
+                embeddings[i] = embedding;
+            }
         }
+        info!(
+            "Embedded {} prompts in {:?}",
+            prompts.len(),
+            start.elapsed()
+        );
 
-        let model: Arc<BertModel> = self.model.as_ref().unwrap().clone();
-        let mut tokenizer: tokio::sync::RwLockWriteGuard<'_, Tokenizer> =
-            self.tokenizer.as_ref().unwrap().write().await;
-        let device = &model.device;
+        Ok(embeddings)
+    }
+}
+
+/// Long-lived handle to an embedding model. The model is loaded the first time something is
+/// embedded and shared by every clone, so a process embedding many PRs only loads it once
+///
+/// ```no_run
+/// use pr_dedupe::bert::Embedder;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let embedder = Embedder::default();
+/// let prs = vec![
+///     vec!["+ : src/a.rs\nfn a() {}\n".to_string()],
+///     vec!["+ : src/b.rs\nfn b() {}\n".to_string()],
+/// ];
+/// let embeddings = embedder.embed_batch(&prs).await?;
+/// assert_eq!(embeddings.len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Embedder {
+    model_id: String,
+    revision: String,
+    batch_size: usize,
+    precision: Precision,
+    cache: Option<EmbeddingCache>,
+    file_weights: FileWeights,
+    bert: Arc<OnceCell<Bert>>,
+}
+
+impl Default for Embedder {
+    fn default() -> Self {
+        Self::new(Bert::MODEL_ID, Bert::REVISION)
+    }
+}
 
-        if let Some(pp) = tokenizer.get_padding_mut() {
-            pp.strategy = tokenizers::PaddingStrategy::BatchLongest
-        } else {
-            let pp = PaddingParams {
-                strategy: tokenizers::PaddingStrategy::BatchLongest,
-                ..Default::default()
-            };
-            tokenizer.with_padding(Some(pp));
+impl Embedder {
+    /// See [`Bert::with_model`], nothing is loaded until the first embedding
+    pub fn new(model_id: &str, revision: &str) -> Self {
+        Self {
+            model_id: model_id.to_string(),
+            revision: revision.to_string(),
+            batch_size: Bert::BATCH_SIZE,
+            precision: Precision::default(),
+            cache: None,
+            file_weights: FileWeights::default(),
+            bert: Arc::new(OnceCell::new()),
         }
+    }
 
-        let tokens = tokenizer.encode_batch(prompts, true).map_err(E::msg)?;
-        let token_ids = tokens
-            .iter()
-            .enumerate()
-            .map(|(i, tokens)| {
-                let tokens = tokens.get_ids().to_vec();
-                let tensor = Tensor::new(tokens.as_slice(), device)?.unsqueeze(0)?;
-                Ok((i, tensor))
+    /// See [`Bert::embed_batch`]
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// See [`Precision`], only applies if set before the model is loaded
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Reuses the embeddings of chunks embedded by earlier runs, see [`EmbeddingCache`]
+    pub fn with_cache(mut self, cache: EmbeddingCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// How much each file counts in a PR's vector. Vectors computed with other weights are
+    /// still compared, so every workflow sharing an index should use the same ones
+    pub fn with_file_weights(mut self, file_weights: FileWeights) -> Self {
+        self.file_weights = file_weights;
+        self
+    }
+
+    pub fn version(&self) -> EmbeddingVersion {
+        Bert::new()
+            .with_model(&self.model_id, &self.revision)
+            .version()
+    }
+
+    /// The loaded model, loading it if nothing was embedded yet
+    pub async fn bert(&self) -> Result<&Bert> {
+        self.bert
+            .get_or_try_init(|| {
+                Bert::new()
+                    .with_model(&self.model_id, &self.revision)
+                    .with_batch_size(self.batch_size)
+                    .with_precision(self.precision)
+                    .build_model_and_tokenizer()
             })
-            .collect::<Result<Vec<_>>>()?;
+            .await
+    }
 
-        let embeddings =
-            vec![Tensor::ones((2, 3), candle_core::DType::F32, device)?; token_ids.len()];
-        // Wrap the embeddings vector in an Arc<Mutex<_>> for thread-safe access
-        let embeddings_arc = Arc::new(Mutex::new(embeddings));
+    /// Embeds a PR's or issue's content, as built by [`crate::build_pr_content`] or
+    /// [`crate::build_issue_content`]
+    pub async fn embed(&self, content: &[String]) -> Result<Vec<f32>> {
+        let mut embeddings = self.embed_batch(&[content.to_vec()]).await?;
+        embeddings
+            .pop()
+            .ok_or(anyhow!("expected 1 embedding, got none"))
+    }
 
-        // Use rayon to compute embeddings in parallel
-        info!("Computing embeddings");
-        let start = std::time::Instant::now();
-        token_ids.par_iter().try_for_each_with(
-            embeddings_arc.clone(),
-            |embeddings_arc, (i, token_ids)| {
-                let token_type_ids = token_ids.zeros_like()?;
-                let embedding = model.forward(token_ids, &token_type_ids)?.squeeze(0)?;
-
-                // Lock the mutex and write the embedding to the correct index
-                let mut embeddings = embeddings_arc
-                    .lock()
-                    .map_err(|e| anyhow!("Mutex error: {}", e))?;
-                embeddings[*i] = embedding;
-
-                Ok::<(), anyhow::Error>(())
-            },
-        )?;
-        info!("Done computing embeddings");
-        info!("Embeddings took {:?} to generate", start.elapsed());
+    /// Embeds many PRs' or issues' content at once, in order. Every file is split in
+    /// chunks, see [`chunks`], and a content's vector is the mean of its files' vectors
+    /// weighted by [`FileWeights`], themselves the mean of their chunks'. Cached chunks
+    /// aren't embedded again and the model isn't even loaded when they all are
+    pub async fn embed_batch(&self, contents: &[Vec<String>]) -> Result<Vec<Vec<f32>>> {
+        let chunked = contents
+            .iter()
+            .map(|content| {
+                let weights = content
+                    .iter()
+                    .map(|entry| self.file_weights.weight(normalize(entry).0.as_deref()))
+                    .collect::<Vec<_>>();
+                // a PR only touching files weighing 0 still needs a vector
+                let unweighted = weights.iter().all(|&w| w == 0.0);
+                content
+                    .iter()
+                    .zip(weights)
+                    .filter(|(_, w)| unweighted || *w > 0.0)
+                    .map(|(entry, w)| (if unweighted { 1.0 } else { w }, chunks(entry)))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let texts = chunked
+            .iter()
+            .flatten()
+            .flat_map(|(_, chunks)| chunks)
+            .collect::<BTreeSet<_>>();
+
+        let version = self.version();
+        let mut embeddings = HashMap::new();
+        if let Some(cache) = &self.cache {
+            for &text in &texts {
+                if let Some(embedding) = cache.get(&version, self.precision, text) {
+                    embeddings.insert(text.clone(), embedding);
+                }
+            }
+        }
+        let cached = embeddings.len();
+
+        let missing = texts
+            .into_iter()
+            .filter(|&text| !embeddings.contains_key(text))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            let embedded = self.bert().await?.embed_batch(&missing).await?;
+            for (text, embedding) in missing.into_iter().zip(embedded) {
+                if let Some(cache) = &self.cache {
+                    if let Err(e) = cache.put(&version, self.precision, &text, &embedding) {
+                        log::warn!("Couldn't cache an embedding | Reason {e}");
+                    }
+                }
+                embeddings.insert(text, embedding);
+            }
+        }
+        if self.cache.is_some() {
+            info!("{cached} of {} chunks were cached", embeddings.len());
+        }
 
-        // Retrieve the final ordered embeddings
-        let embeddings_arc = Arc::try_unwrap(embeddings_arc)
-            .map_err(|_| anyhow!("Arc unwrap failed"))?
-            .into_inner()
-            .map_err(|e| anyhow!("Mutex error: {}", e))?;
+        Ok(chunked
+            .iter()
+            .map(|entries| {
+                weighted_mean(entries.iter().map(|(weight, chunks)| {
+                    let chunks = chunks.iter().map(|text| (1.0, &embeddings[text]));
+                    (*weight, weighted_mean(chunks))
+                }))
+            })
+            .collect())
+    }
+}
 
-        let stacked_embeddings = Tensor::stack(&embeddings_arc, 0)?;
+/// Splits a content entry in the texts embedded separately, at line boundaries. File
+/// chunks start with the file's path, without the commit it was downloaded at, so unchanged
+/// files embed the same across pushes
+///
+/// ```
+/// use pr_dedupe::bert::chunks;
+///
+/// let entry = "M : https://github.com/acme/app/raw/abc/src/lib.rs\nfn a() {}\n";
+/// assert_eq!(chunks(entry), ["src/lib.rs\nM\nfn a() {}"]);
+/// ```
+pub fn chunks(entry: &str) -> Vec<String> {
+    let (path, body) = normalize(entry);
+    let header = path.map(|path| format!("{path}\n")).unwrap_or_default();
+
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    for line in body.lines() {
+        if !chunk.is_empty() && chunk.len() + line.len() >= CHUNK_CHARS {
+            chunks.push(format!("{header}{chunk}"));
+            chunk.clear();
+        }
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(line);
+    }
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(format!("{header}{chunk}"));
+    }
+    chunks.truncate(MAX_CHUNKS);
+    chunks
+}
 
-        Ok(EmbeddingResponse::Bert(stacked_embeddings))
+/// Element-wise weighted mean of same length vectors
+fn weighted_mean<V: AsRef<[f32]>>(vectors: impl Iterator<Item = (f32, V)>) -> Vec<f32> {
+    let mut sum: Vec<f32> = vec![];
+    let mut total = 0.0;
+    for (weight, vector) in vectors {
+        let vector = vector.as_ref();
+        if sum.is_empty() {
+            sum = vec![0.0; vector.len()];
+        }
+        sum.iter_mut()
+            .zip(vector)
+            .for_each(|(s, v)| *s += weight * v);
+        total += weight;
+    }
+    if total > 0.0 {
+        sum.iter_mut().for_each(|s| *s /= total);
     }
+    sum
 }
 
+/// Loads the default model to embed one PR's content, use an [`Embedder`] to embed more
 pub async fn generate_embeddings(content: Vec<String>, _max_tokens: usize) -> Result<Vec<f32>> {
-    let bert = Bert::new().build_model_and_tokenizer().await?;
-    let x = content.join(" ");
-    // TODO: chunk later
-    let content = [x.as_str()].to_vec();
-
-    // chunk input text
-    // use huggingface tokenizer for text splitter?
-    // text_splitter::TextSplitter::new(bert.tokenizer.unwrap().borrow().into());
-    // let splitter = text_splitter::TextSplitter::default().with_trim_chunks(true);
-
-    // let chunked_pr_content = splitter.chunks(content, max_tokens).collect::<Vec<&str>>();
-
-    // Ok(bert
-    //     .generate_embeddings(chunked_pr_content)
-    //     .await
-    //     .unwrap()
-    //     .to_vec()
-    //     .unwrap())
-
-    // let content = content.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
-
-    Ok(bert
-        .generate_embeddings(content)
-        .await
-        .unwrap()
-        .to_vec()
-        .unwrap())
+    Embedder::default().embed(&content).await
 }
 
 #[cfg(test)]
@@ -286,13 +657,43 @@ mod test {
         Ok(cosine_similarity)
     }
 
+    #[test]
+    fn precisions_share_a_version() {
+        let embedder = Embedder::new(Bert::MODEL_ID, Bert::REVISION);
+
+        assert_eq!(
+            embedder.clone().with_precision(Precision::Int8).version(),
+            embedder.with_precision(Precision::F32).version()
+        );
+    }
+
+    #[test]
+    fn long_files_are_split_at_lines() {
+        let line = "x".repeat(200);
+        let entry =
+            format!("+ : https://github.com/acme/app/raw/abc/a.rs\n{line}\n{line}\n{line}\n");
+
+        let chunks = chunks(&entry);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], format!("a.rs\n+\n{line}\n{line}"));
+        assert_eq!(chunks[1], format!("a.rs\n{line}"));
+    }
+
+    #[test]
+    fn files_are_averaged_by_weight() {
+        let vectors = [(1.0, vec![1.0, 2.0]), (3.0, vec![3.0, 0.0])];
+        assert_eq!(weighted_mean(vectors.into_iter()), [2.5, 0.5]);
+    }
+
     #[tokio::test]
     async fn test_file_example() {
         set_hf_home_env();
 
-        // not the best approach to re-init bert, but works
-        let pr_1_embedding = generate_embeddings(
-            [r#"PR Dedupe
+        let embedder = Embedder::default();
+
+        let pr_1_embedding = embedder
+            .embed(&[r#"PR Dedupe
 
             on:
               pull_request:
@@ -312,15 +713,12 @@ mod test {
                       UPSTASH_VECTOR_REST_TOKEN: ${{ secrets.PR_DEDUPE_UPSTASH_VECTOR_REST_TOKEN }}
 
             "#
-            .to_string()]
-            .to_vec(),
-            384,
-        )
-        .await
-        .unwrap();
+            .to_string()])
+            .await
+            .unwrap();
 
-        let pr_2_embedding = generate_embeddings(
-            [r#"PR Dedupe
+        let pr_2_embedding = embedder
+            .embed(&[r#"PR Dedupe
 
             on:
               pull_request:
@@ -340,15 +738,12 @@ mod test {
                       UPSTASH_VECTOR_REST_TOKEN: ${{ secrets.PR_DEDUPE_UPSTASH_VECTOR_REST_TOKEN }}
 
             "#
-            .to_string()]
-            .to_vec(),
-            384,
-        )
-        .await
-        .unwrap();
+            .to_string()])
+            .await
+            .unwrap();
 
-        let pr_3_embedding = generate_embeddings(
-            [r#"pr dedup
+        let pr_3_embedding = embedder
+            .embed(&[r#"pr dedup
 
             on:
               pull_request:
@@ -365,12 +760,9 @@ mod test {
                       UPSTASH_VECTOR_REST_TOKEN: ${{ secrets.PR_DEDUPE_UPSTASH_VECTOR_REST_TOKEN }}
 
             "#
-            .to_string()]
-            .to_vec(),
-            384,
-        )
-        .await
-        .unwrap();
+            .to_string()])
+            .await
+            .unwrap();
 
         let device = &Bert::device();
 
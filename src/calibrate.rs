@@ -0,0 +1,121 @@
+use std::fmt::Write;
+
+use crate::{
+    eval::{cosine_similarity, upstash_score},
+    paths::PathFrequencies,
+    utils::{EmbeddedItem, ScoreWeights},
+};
+
+/// Quantiles reported alongside the recommended threshold
+const QUANTILES: [f32; 5] = [0.5, 0.9, 0.95, 0.99, 0.999];
+
+/// The similarity distribution of a repo's unrelated PRs, and the lowest `min_similarity`
+/// that keeps them from being reported too often.
+///
+/// Sampled PRs are assumed to be unrelated to each other, duplicates being rare enough not
+/// to move the distribution, except for exact duplicates which are left out
+///
+/// ```
+/// use pr_dedupe::{calibrate::Calibration, utils::ScoreWeights};
+///
+/// assert!(Calibration::new(&[], &ScoreWeights::default(), 0.01).is_none());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub sampled: usize,
+    /// scored pairs of sampled PRs
+    pub pairs: usize,
+    pub target_false_positive_rate: f32,
+    /// lowest `min_similarity` at which at most `target_false_positive_rate` of the pairs match
+    pub threshold: u8,
+    /// share of the pairs scoring at least `threshold`
+    pub false_positive_rate: f32,
+    /// (quantile, score in percentage) of the background distribution
+    pub quantiles: Vec<(f32, f32)>,
+}
+
+impl Calibration {
+    /// Scores every pair of `items` the way a query scores a match, `None` without at least
+    /// one pair
+    pub fn new(items: &[EmbeddedItem], weights: &ScoreWeights, target: f32) -> Option<Self> {
+        let path_frequencies = PathFrequencies::new(items.iter().filter_map(|i| i.paths.as_ref()));
+        let mut scores = Vec::new();
+        for (i, a) in items.iter().enumerate() {
+            for b in &items[i + 1..] {
+                let score = a.score(
+                    upstash_score(cosine_similarity(&a.embedding, &b.embedding)),
+                    b.fingerprint.as_ref(),
+                    b.lexical.as_ref(),
+                    b.paths.as_ref(),
+                    &path_frequencies,
+                    weights,
+                );
+                if !score.exact {
+                    scores.push(score.percentage);
+                }
+            }
+        }
+        Self::from_scores(items.len(), scores, target)
+    }
+
+    fn from_scores(sampled: usize, mut scores: Vec<f32>, target: f32) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        scores.sort_by(f32::total_cmp);
+
+        let rate = |threshold: u8| {
+            let below = scores.partition_point(|&s| s < threshold as f32);
+            (scores.len() - below) as f32 / scores.len() as f32
+        };
+        let threshold = (0..=100).find(|&t| rate(t) <= target).unwrap_or(100);
+
+        Some(Self {
+            sampled,
+            pairs: scores.len(),
+            target_false_positive_rate: target,
+            threshold,
+            false_positive_rate: rate(threshold),
+            quantiles: QUANTILES
+                .iter()
+                .map(|&q| {
+                    let i = ((scores.len() - 1) as f32 * q).round() as usize;
+                    (q, scores[i])
+                })
+                .collect(),
+        })
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut report = format!(
+            "recommended min_similarity: {} ({:.2}% of {} pairs from {} sampled PRs match, target {:.2}%)\n\n\
+             | quantile | similarity |\n\
+             |---|---|\n",
+            self.threshold,
+            self.false_positive_rate * 100.0,
+            self.pairs,
+            self.sampled,
+            self.target_false_positive_rate * 100.0,
+        );
+        for (q, score) in &self.quantiles {
+            let _ = writeln!(report, "| {q} | {score:.1} |");
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recommends_the_lowest_threshold_under_the_target() {
+        let scores = (0..100).map(|s| s as f32).collect();
+
+        let calibration = Calibration::from_scores(15, scores, 0.05).unwrap();
+
+        assert_eq!(calibration.threshold, 95);
+        assert_eq!(calibration.false_positive_rate, 0.05);
+        assert_eq!(calibration.quantiles[0], (0.5, 50.0));
+    }
+}
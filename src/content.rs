@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use futures::stream::StreamExt;
+use log::{info, warn};
+
+use crate::{
+    files_to_ignore::FILES_TO_IGNORE, fingerprint::normalize, http::HttpClient, id::ItemId,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum FileAction {
+    Added,
+    Modified,
+    Removed,
+    Renamed,
+}
+
+impl From<FileAction> for char {
+    fn from(val: FileAction) -> Self {
+        match val {
+            FileAction::Added => '+',
+            FileAction::Modified => 'M',
+            FileAction::Removed => '-',
+            FileAction::Renamed => '^',
+        }
+    }
+}
+
+/// Files touched by a PR, grouped by how they were changed
+#[derive(Debug, Default, Clone)]
+pub struct PrFiles {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+    /// new paths of renamed files
+    pub renamed: Vec<String>,
+    /// old path of renamed files, by new path. The changed files the action gets only list
+    /// new paths, see [`crate::github::GitHub::pull_files`]
+    pub renamed_from: HashMap<String, String>,
+}
+
+impl PrFiles {
+    /// builds the file lists from the comma separated values passed to the cli
+    ///
+    /// ```
+    /// use pr_dedupe::PrFiles;
+    ///
+    /// let files = PrFiles::from_csv("src/lib.rs,README.md", "", "", "");
+    /// assert_eq!(files.added, ["src/lib.rs", "README.md"]);
+    /// assert!(files.removed.is_empty());
+    /// ```
+    pub fn from_csv(added: &str, modified: &str, removed: &str, renamed: &str) -> Self {
+        let split = |files: &str| {
+            files
+                .split(',')
+                .filter(|file| !file.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        Self {
+            added: split(added),
+            modified: split(modified),
+            removed: split(removed),
+            renamed: split(renamed),
+            renamed_from: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        [&self.added, &self.modified, &self.removed, &self.renamed]
+            .iter()
+            .all(|files| files.is_empty())
+    }
+}
+
+/// Downloads the PR's added/modified files at `sha` and turns every touched file into
+/// the text that gets embedded. With the PR's `base_sha`, removed files are represented by
+/// their deleted content and renamed ones by their old path and how similar they still are
+///
+/// ```no_run
+/// use pr_dedupe::{build_pr_content, http::HttpClient, id::ItemKind, ItemId, PrFiles};
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let http = HttpClient::new(reqwest::Client::builder())?;
+/// let pr = ItemId::new("github.com", "cs50victor/pr_dedupe", ItemKind::Pull, 2)?;
+/// let files = PrFiles::from_csv("action.yml", "src/main.rs", "", "");
+/// let content = build_pr_content(&http, &pr, "main", None, &files).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn build_pr_content(
+    http: &HttpClient,
+    pr: &ItemId,
+    sha: &str,
+    base_sha: Option<&str>,
+    files: &PrFiles,
+) -> Result<Vec<String>> {
+    if files.is_empty() {
+        info!("this pr has no content, it's probably a bot or spam");
+        return Ok([" ".to_string()].to_vec());
+    }
+
+    let raw_url_prefix = format!("https://{}/{}/raw/{sha}/", pr.host, pr.repo_name());
+    let base_url_prefix =
+        base_sha.map(|base| format!("https://{}/{}/raw/{base}/", pr.host, pr.repo_name()));
+
+    info!("raw_url_prefix {}", &raw_url_prefix);
+
+    let pr_files = files
+        .added
+        .iter()
+        .map(|file| (file, FileAction::Added))
+        .chain(
+            files
+                .modified
+                .iter()
+                .map(|file| (file, FileAction::Modified)),
+        )
+        .filter(|(file, _)| !is_ignored(file))
+        .map(|(file, action)| (format!("{}{file}", &raw_url_prefix), action));
+
+    info!(
+        "downloading PR files | {:?}",
+        pr_files.clone().collect::<Vec<_>>()
+    );
+
+    let mut pr_content = futures::stream::iter(pr_files.map(|(path, file_type)| async move {
+        let content = download(http, &path).await?;
+        Ok(parse(file_type, &path, Some(&content)))
+    }))
+    .buffer_unordered(10)
+    .collect::<Vec<Result<String>>>()
+    .await
+    .into_iter()
+    .collect::<Result<Vec<String>>>()?;
+
+    let (raw_url_prefix, base_url_prefix) = (&raw_url_prefix, &base_url_prefix);
+    let removed = files.removed.iter().map(|file| async move {
+        let path = format!(
+            "{}{file}",
+            base_url_prefix.as_ref().unwrap_or(raw_url_prefix)
+        );
+        let deleted = match (&base_url_prefix, is_ignored(file)) {
+            (Some(_), false) => download(http, &path)
+                .await
+                .map_err(|e| warn!("keeping only the path of removed {file} | Reason {e}"))
+                .ok(),
+            _ => None,
+        };
+        parse(FileAction::Removed, &path, deleted.as_deref())
+    });
+    let renamed = files.renamed.iter().map(|file| async move {
+        let path = format!("{raw_url_prefix}{file}");
+        let rename = match (files.renamed_from.get(file), &base_url_prefix) {
+            (Some(old), Some(base)) => {
+                let old_path = format!("{base}{old}");
+                let (old_content, new_content) =
+                    futures::join!(download(http, &old_path), download(http, &path));
+                match (old_content, new_content) {
+                    (Ok(old_content), Ok(new_content)) => Some(format!(
+                        "renamed from {old} ({:.0}% similar)",
+                        line_similarity(&old_content, &new_content) * 100.0
+                    )),
+                    _ => Some(format!("renamed from {old}")),
+                }
+            }
+            (Some(old), None) => Some(format!("renamed from {old}")),
+            (None, _) => None,
+        };
+        parse(FileAction::Renamed, &path, rename.as_deref())
+    });
+    pr_content.extend(
+        futures::stream::iter(removed)
+            .buffered(10)
+            .collect::<Vec<_>>()
+            .await,
+    );
+    pr_content.extend(
+        futures::stream::iter(renamed)
+            .buffered(10)
+            .collect::<Vec<_>>()
+            .await,
+    );
+
+    Ok(pr_content)
+}
+
+fn is_ignored(file: &str) -> bool {
+    FILES_TO_IGNORE.iter().any(|&suffix| file.ends_with(suffix))
+}
+
+async fn download(http: &HttpClient, path: &str) -> Result<String> {
+    let resp = http
+        .send(http.get(path))
+        .await
+        .and_then(|resp| Ok(resp.error_for_status()?))
+        .map_err(|e| anyhow!("Couldn't download {path} | Reason {e:?}"))?;
+    let resp_bytes = resp.bytes().await?;
+    Ok(std::str::from_utf8(&resp_bytes)?.to_string())
+}
+
+/// Share of lines both versions of a file have, from 0 to 1, like git's rename similarity
+fn line_similarity(old: &str, new: &str) -> f32 {
+    let mut old_lines = HashMap::new();
+    for line in old.lines().map(str::trim_end) {
+        *old_lines.entry(line).or_insert(0) += 1;
+    }
+    let mut common = 0;
+    for line in new.lines().map(str::trim_end) {
+        if let Some(count) = old_lines.get_mut(line).filter(|count| **count > 0) {
+            *count -= 1;
+            common += 1;
+        }
+    }
+    match old.lines().count() + new.lines().count() {
+        0 => 1.0,
+        total => 2.0 * common as f32 / total as f32,
+    }
+}
+
+/// Turns an issue's title, body and labels into the text that gets embedded
+///
+/// ```
+/// use pr_dedupe::build_issue_content;
+///
+/// let content = build_issue_content("Crash on empty PRs", "", &["bug".to_string()]);
+/// assert_eq!(content, ["Crash on empty PRs\n", "labels : bug\n"]);
+/// ```
+pub fn build_issue_content(title: &str, body: &str, labels: &[String]) -> Vec<String> {
+    let mut issue_content = vec![format!("{}\n{}", title.trim(), body.trim())];
+
+    if !labels.is_empty() {
+        issue_content.push(format!("labels : {}\n", labels.join(", ")));
+    }
+
+    issue_content
+}
+
+/// max chars of the summary stored alongside a vector
+pub const SUMMARY_LEN: usize = 2000;
+
+/// Condenses content into the text stored next to its vector, which candidates are reranked
+/// on. Touched paths come first, so they survive when the rest is cut off
+///
+/// ```
+/// use pr_dedupe::content::summarize;
+///
+/// let content = ["M : https://github.com/acme/app/raw/abc/src/lib.rs\nfn a() {}\n".to_string()];
+/// assert_eq!(summarize(&content), "src/lib.rs\nM\nfn a() {}");
+/// ```
+pub fn summarize(content: &[String]) -> String {
+    let (paths, bodies): (Vec<_>, Vec<_>) = content.iter().map(|entry| normalize(entry)).unzip();
+
+    let summary = paths
+        .into_iter()
+        .flatten()
+        .chain(bodies.into_iter().filter(|body| !body.is_empty()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    match summary.char_indices().nth(SUMMARY_LEN) {
+        Some((end, _)) => summary[..end].to_string(),
+        None => summary,
+    }
+}
+
+fn parse(file_type: FileAction, path: &str, content: Option<&str>) -> String {
+    let symbol: char = file_type.into();
+    match content {
+        Some(c) => {
+            info!("parsed {path}'s content");
+            format!("{symbol} : {path}\n{c}\n")
+        }
+        None => format!("{symbol} : {path}\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pr_files_from_csv_skips_empty_entries() {
+        let files = PrFiles::from_csv("src/a.rs,src/b.rs", "", "old.rs", "");
+
+        assert_eq!(files.added, ["src/a.rs", "src/b.rs"]);
+        assert!(files.modified.is_empty());
+        assert_eq!(files.removed, ["old.rs"]);
+        assert!(files.renamed.is_empty());
+        assert!(!files.is_empty());
+        assert!(PrFiles::from_csv("", "", "", "").is_empty());
+    }
+
+    #[test]
+    fn renames_are_as_similar_as_the_lines_they_keep() {
+        let old = "fn a() {}\nfn b() {}\nfn c() {}\n";
+
+        assert_eq!(line_similarity(old, old), 1.0);
+        assert_eq!(
+            line_similarity(old, "fn a() {}\nfn b() {}\nfn d() {}\n"),
+            2.0 / 3.0
+        );
+        assert_eq!(line_similarity(old, ""), 0.0);
+    }
+}
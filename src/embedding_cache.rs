@@ -0,0 +1,112 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use log::warn;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    bert::{EmbeddingVersion, Precision},
+    fingerprint::hex,
+};
+
+/// Embeddings of chunks of content already seen, on disk, so a push touching one file only
+/// embeds that file again. Each model version and precision gets its own folder, and each
+/// chunk a file named after its text's sha256 holding the vector as little endian f32s.
+///
+/// The folder can be kept between workflow runs with actions/cache. Entries are only ever
+/// added, so the cache can be restored from any earlier run
+#[derive(Debug, Clone)]
+pub struct EmbeddingCache {
+    dir: PathBuf,
+}
+
+impl EmbeddingCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, version: &EmbeddingVersion, precision: Precision, text: &str) -> PathBuf {
+        // quantized vectors are close to F32 ones but not the same, see `check-precision`
+        let version = format!(
+            "{}@{}/{}/{precision:?}",
+            version.model_id, version.revision, version.pipeline_version
+        );
+        self.dir
+            .join(&hex(&Sha256::digest(version))[..16])
+            .join(hex(&Sha256::digest(text)))
+    }
+
+    /// The cached embedding of `text`, `None` when it's missing or unreadable
+    pub fn get(
+        &self,
+        version: &EmbeddingVersion,
+        precision: Precision,
+        text: &str,
+    ) -> Option<Vec<f32>> {
+        let bytes = fs::read(self.path(version, precision, text)).ok()?;
+        if bytes.is_empty() || bytes.len() % 4 != 0 {
+            warn!("ignoring corrupt cached embedding of {} bytes", bytes.len());
+            return None;
+        }
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        )
+    }
+
+    pub fn put(
+        &self,
+        version: &EmbeddingVersion,
+        precision: Precision,
+        text: &str,
+        embedding: &[f32],
+    ) -> Result<()> {
+        let path = self.path(version, precision, text);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let bytes = embedding
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        // written aside then renamed, so a run cancelled mid-write can't leave a truncated
+        // vector behind for the next one
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(partial, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_embeddings_are_per_model_version_and_precision() {
+        let dir = std::env::temp_dir().join(format!("pr_dedupe_cache_{}", std::process::id()));
+        let cache = EmbeddingCache::new(&dir);
+        let version = |model_id: &str| EmbeddingVersion {
+            model_id: model_id.to_string(),
+            revision: "main".to_string(),
+            pipeline_version: 2,
+        };
+
+        cache
+            .put(&version("a"), Precision::F32, "fn a() {}", &[0.5, -1.0])
+            .unwrap();
+        let hit = cache.get(&version("a"), Precision::F32, "fn a() {}");
+        let other_text = cache.get(&version("a"), Precision::F32, "fn b() {}");
+        let other_model = cache.get(&version("b"), Precision::F32, "fn a() {}");
+        let other_precision = cache.get(&version("a"), Precision::Int8, "fn a() {}");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(hit, Some(vec![0.5, -1.0]));
+        assert_eq!(
+            (other_text, other_model, other_precision),
+            (None, None, None)
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use candle_core::{
+    quantized::{GgmlDType, QMatMul, QTensor},
+    DType, Device, Result, Tensor, D,
+};
+use candle_nn::{embedding, layer_norm, linear, Embedding, LayerNorm, Linear, Module, VarBuilder};
+use candle_transformers::models::bert::HiddenAct;
+use serde::Deserialize;
+
+/// The parts of a BERT `config.json` the encoder needs. candle's bert `Config` keeps its
+/// fields private
+#[derive(Deserialize, Debug, Clone)]
+pub struct EncoderConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    pub hidden_act: HiddenAct,
+    pub max_position_embeddings: usize,
+    pub type_vocab_size: usize,
+    pub layer_norm_eps: f64,
+    #[serde(default)]
+    pub model_type: Option<String>,
+}
+
+struct Embeddings {
+    word: Embedding,
+    position: Embedding,
+    token_type: Embedding,
+    layer_norm: LayerNorm,
+}
+
+impl Embeddings {
+    fn load(vb: VarBuilder, c: &EncoderConfig) -> Result<Self> {
+        Ok(Self {
+            word: embedding(c.vocab_size, c.hidden_size, vb.pp("word_embeddings"))?,
+            position: embedding(
+                c.max_position_embeddings,
+                c.hidden_size,
+                vb.pp("position_embeddings"),
+            )?,
+            token_type: embedding(
+                c.type_vocab_size,
+                c.hidden_size,
+                vb.pp("token_type_embeddings"),
+            )?,
+            layer_norm: layer_norm(c.hidden_size, c.layer_norm_eps, vb.pp("LayerNorm"))?,
+        })
+    }
+
+    fn forward(&self, input_ids: &Tensor, token_type_ids: &Tensor) -> Result<Tensor> {
+        let seq_len = input_ids.dim(1)?;
+        let positions = Tensor::arange(0, seq_len as u32, input_ids.device())?;
+        let embeddings = (self.word.forward(input_ids)?
+            + self.token_type.forward(token_type_ids)?)?
+        .broadcast_add(&self.position.forward(&positions)?)?;
+        self.layer_norm.forward(&embeddings)
+    }
+}
+
+/// A linear layer, with its weights either as loaded or quantized
+enum Dense {
+    F32(Linear),
+    Quantized {
+        weight: QMatMul,
+        bias: Option<Tensor>,
+    },
+}
+
+impl Dense {
+    fn load(in_dim: usize, out_dim: usize, vb: VarBuilder) -> Result<Self> {
+        linear(in_dim, out_dim, vb).map(Dense::F32)
+    }
+
+    fn quantize(self, dtype: GgmlDType) -> Result<Self> {
+        match self {
+            Dense::F32(linear) => Ok(Dense::Quantized {
+                weight: QMatMul::from_qtensor(QTensor::quantize(linear.weight(), dtype)?)?,
+                bias: linear.bias().cloned(),
+            }),
+            quantized => Ok(quantized),
+        }
+    }
+}
+
+impl Module for Dense {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Dense::F32(linear) => linear.forward(xs),
+            Dense::Quantized { weight, bias } => {
+                let xs = weight.forward(xs)?;
+                match bias {
+                    Some(bias) => xs.broadcast_add(bias),
+                    None => Ok(xs),
+                }
+            }
+        }
+    }
+}
+
+struct Layer {
+    query: Dense,
+    key: Dense,
+    value: Dense,
+    attention_output: Dense,
+    attention_norm: LayerNorm,
+    intermediate: Dense,
+    output: Dense,
+    output_norm: LayerNorm,
+    heads: usize,
+    act: HiddenAct,
+}
+
+impl Layer {
+    fn load(vb: VarBuilder, c: &EncoderConfig) -> Result<Self> {
+        let (hidden, eps) = (c.hidden_size, c.layer_norm_eps);
+        let attention = vb.pp("attention");
+        Ok(Self {
+            query: Dense::load(hidden, hidden, attention.pp("self.query"))?,
+            key: Dense::load(hidden, hidden, attention.pp("self.key"))?,
+            value: Dense::load(hidden, hidden, attention.pp("self.value"))?,
+            attention_output: Dense::load(hidden, hidden, attention.pp("output.dense"))?,
+            attention_norm: layer_norm(hidden, eps, attention.pp("output.LayerNorm"))?,
+            intermediate: Dense::load(hidden, c.intermediate_size, vb.pp("intermediate.dense"))?,
+            output: Dense::load(c.intermediate_size, hidden, vb.pp("output.dense"))?,
+            output_norm: layer_norm(hidden, eps, vb.pp("output.LayerNorm"))?,
+            heads: c.num_attention_heads,
+            act: c.hidden_act,
+        })
+    }
+
+    fn quantize(self, dtype: GgmlDType) -> Result<Self> {
+        Ok(Self {
+            query: self.query.quantize(dtype)?,
+            key: self.key.quantize(dtype)?,
+            value: self.value.quantize(dtype)?,
+            attention_output: self.attention_output.quantize(dtype)?,
+            intermediate: self.intermediate.quantize(dtype)?,
+            output: self.output.quantize(dtype)?,
+            ..self
+        })
+    }
+
+    /// `mask_bias` is 0 for tokens that can be attended to and very negative for padding
+    fn forward(&self, xs: &Tensor, mask_bias: &Tensor) -> Result<Tensor> {
+        let (batch, seq_len, hidden) = xs.dims3()?;
+        let head_size = hidden / self.heads;
+        // candle runs one matmul per sequence on 3d inputs, a single 2d one is much faster
+        let xs = &xs.reshape((batch * seq_len, hidden))?;
+        let split_heads = |t: Tensor| {
+            t.reshape((batch, seq_len, self.heads, head_size))?
+                .transpose(1, 2)?
+                .contiguous()
+        };
+        let q = split_heads(self.query.forward(xs)?)?;
+        let k = split_heads(self.key.forward(xs)?)?;
+        let v = split_heads(self.value.forward(xs)?)?;
+
+        let scores = (q.matmul(&k.t()?)? / (head_size as f64).sqrt())?.broadcast_add(mask_bias)?;
+        let probs = candle_nn::ops::softmax(&scores, D::Minus1)?;
+        let context = probs
+            .matmul(&v)?
+            .transpose(1, 2)?
+            .reshape((batch * seq_len, hidden))?;
+
+        let attended = self
+            .attention_norm
+            .forward(&(self.attention_output.forward(&context)? + xs)?)?;
+        let intermediate = self.intermediate.forward(&attended)?;
+        let intermediate = match self.act {
+            HiddenAct::Gelu => intermediate.gelu_erf()?,
+            HiddenAct::GeluApproximate => intermediate.gelu()?,
+            HiddenAct::Relu => intermediate.relu()?,
+        };
+        self.output_norm
+            .forward(&(self.output.forward(&intermediate)? + attended)?)?
+            .reshape((batch, seq_len, hidden))
+    }
+}
+
+/// BERT encoder running a whole padded batch in one forward pass. candle's `BertModel` has
+/// no attention mask, so padding would change every sequence's hidden states
+pub struct Encoder {
+    embeddings: Embeddings,
+    layers: Vec<Layer>,
+    pub device: Device,
+}
+
+impl Encoder {
+    /// Loads weights stored with or without the `bert.`/`roberta.` prefix
+    pub fn load(vb: VarBuilder, config: &EncoderConfig) -> Result<Self> {
+        let load = |vb: VarBuilder| {
+            let embeddings = Embeddings::load(vb.pp("embeddings"), config)?;
+            let layers = (0..config.num_hidden_layers)
+                .map(|i| Layer::load(vb.pp(format!("encoder.layer.{i}")), config))
+                .collect::<Result<Vec<_>>>()?;
+            Ok::<_, candle_core::Error>((embeddings, layers))
+        };
+        let (embeddings, layers) = match (load(vb.clone()), &config.model_type) {
+            (Ok(loaded), _) => loaded,
+            (Err(e), Some(model_type)) => load(vb.pp(model_type)).map_err(|_| e)?,
+            (Err(e), None) => return Err(e),
+        };
+        Ok(Self {
+            embeddings,
+            layers,
+            device: vb.device().clone(),
+        })
+    }
+
+    /// Quantizes the linear layers' weights, which hold nearly all of the compute, e.g. to
+    /// `GgmlDType::Q8_0`. Embeddings and layer norms stay F32. The hidden size must be a
+    /// multiple of the quantization's block size
+    pub fn quantize(self, dtype: GgmlDType) -> Result<Self> {
+        Ok(Self {
+            layers: self
+                .layers
+                .into_iter()
+                .map(|layer| layer.quantize(dtype))
+                .collect::<Result<_>>()?,
+            ..self
+        })
+    }
+
+    /// Hidden states of every token, (batch, seq_len, hidden_size). All inputs are
+    /// (batch, seq_len), `attention_mask` being 1 for real tokens and 0 for padding
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: &Tensor,
+    ) -> Result<Tensor> {
+        let (batch, seq_len) = attention_mask.dims2()?;
+        let mask_bias = ((attention_mask.to_dtype(DType::F32)? - 1.0)? * 10_000.0)?
+            .reshape((batch, 1, 1, seq_len))?;
+
+        let mut xs = self.embeddings.forward(input_ids, token_type_ids)?;
+        for layer in &self.layers {
+            xs = layer.forward(&xs, &mask_bias)?;
+        }
+        Ok(xs)
+    }
+}
+
+/// Mean of each sequence's hidden states, leaving padding out, (batch, hidden_size)
+pub fn mean_pool(hidden: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    let mask = attention_mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+    let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+    summed.broadcast_div(&mask.sum(1)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_pool_skips_padding() {
+        let device = Device::Cpu;
+        let hidden = Tensor::new(&[[[1f32, 2.], [3., 4.], [100., 100.]]], &device).unwrap();
+        let mask = Tensor::new(&[[1u32, 1, 0]], &device).unwrap();
+
+        let pooled = mean_pool(&hidden, &mask).unwrap().to_vec2::<f32>().unwrap();
+
+        assert_eq!(pooled, [[2., 3.]]);
+    }
+
+    /// Encoder with random weights, dims being multiples of quantization block sizes
+    fn random_encoder(device: &Device) -> Encoder {
+        let varmap = candle_nn::VarMap::new();
+        let config = EncoderConfig {
+            vocab_size: 10,
+            hidden_size: 64,
+            num_hidden_layers: 2,
+            num_attention_heads: 2,
+            intermediate_size: 128,
+            hidden_act: HiddenAct::Gelu,
+            max_position_embeddings: 16,
+            type_vocab_size: 2,
+            layer_norm_eps: 1e-12,
+            model_type: None,
+        };
+        Encoder::load(
+            VarBuilder::from_varmap(&varmap, DType::F32, device),
+            &config,
+        )
+        .unwrap()
+    }
+
+    fn embed(encoder: &Encoder, ids: &[&[u32]], mask: &[&[u32]]) -> Vec<Vec<f32>> {
+        let ids = Tensor::new(ids.to_vec(), &encoder.device).unwrap();
+        let mask = Tensor::new(mask.to_vec(), &encoder.device).unwrap();
+        let hidden = encoder
+            .forward(&ids, &ids.zeros_like().unwrap(), &mask)
+            .unwrap();
+        mean_pool(&hidden, &mask).unwrap().to_vec2::<f32>().unwrap()
+    }
+
+    #[test]
+    fn padding_doesnt_change_a_sequence_embedding() {
+        let encoder = random_encoder(&Device::Cpu);
+
+        let alone = embed(&encoder, &[&[1, 2, 3]], &[&[1, 1, 1]]);
+        let batched = embed(
+            &encoder,
+            &[&[1, 2, 3, 0, 0], &[4, 5, 6, 7, 8]],
+            &[&[1, 1, 1, 0, 0], &[1; 5]],
+        );
+
+        for (a, b) in alone[0].iter().zip(&batched[0]) {
+            assert!((a - b).abs() < 1e-5, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn int8_embeddings_stay_close_to_f32() {
+        let ids: &[&[u32]] = &[&[1, 2, 3, 4, 5, 6], &[7, 8, 9, 1, 0, 0]];
+        let mask: &[&[u32]] = &[&[1; 6], &[1, 1, 1, 1, 0, 0]];
+        let encoder = random_encoder(&Device::Cpu);
+        let f32 = embed(&encoder, ids, mask);
+
+        let quantized = embed(&encoder.quantize(GgmlDType::Q8_0).unwrap(), ids, mask);
+
+        // well within `Precision::TOLERANCE`, which lets both precisions share an index
+        for (a, b) in f32.iter().zip(&quantized) {
+            let cosine = crate::eval::cosine_similarity(a, b);
+            assert!(cosine > 0.999, "cosine {cosine}");
+        }
+    }
+}
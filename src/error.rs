@@ -0,0 +1,105 @@
+use std::process::ExitCode;
+
+use clap::ValueEnum;
+use log::{error, warn};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Missing or invalid env variables and cli arguments
+    #[error("{0:#}")]
+    Config(anyhow::Error),
+
+    /// Downloading PR files or talking to the GitHub api failed
+    #[error("{0:#}")]
+    Network(anyhow::Error),
+
+    /// Loading the model or computing embeddings failed
+    #[error("{0:#}")]
+    Model(anyhow::Error),
+
+    /// The vector database rejected a request or couldn't be reached
+    #[error("{0:#}")]
+    Backend(anyhow::Error),
+}
+
+/// What to do when the vector database is down
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum BackendErrorPolicy {
+    /// fail the check
+    #[default]
+    Fail,
+    /// log a warning and let the check pass without reporting similar PRs
+    Pass,
+}
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Network(_) => 3,
+            Error::Model(_) => 4,
+            Error::Backend(_) => 5,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "pr_dedupe configuration error",
+            Error::Network(_) => "pr_dedupe network error",
+            Error::Model(_) => "pr_dedupe model error",
+            Error::Backend(_) => "pr_dedupe vector database error",
+        }
+    }
+
+    /// Logs the error, emits a GitHub annotation and returns the process' exit code
+    pub fn report(&self, policy: BackendErrorPolicy) -> ExitCode {
+        if let (Error::Backend(_), BackendErrorPolicy::Pass) = (self, policy) {
+            warn!("{self}");
+            println!(
+                "::warning title={}::{}",
+                self.title(),
+                escape_data(&self.to_string())
+            );
+            return ExitCode::SUCCESS;
+        }
+
+        error!("{self}");
+        println!(
+            "::error title={}::{}",
+            self.title(),
+            escape_data(&self.to_string())
+        );
+        ExitCode::from(self.exit_code())
+    }
+}
+
+/// Escapes a workflow command's message, see https://github.com/actions/toolkit/blob/main/packages/core/src/command.ts
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_errors_soft_pass_only_when_allowed() {
+        let err = Error::Backend(anyhow::anyhow!("upstash is down"));
+
+        assert_eq!(err.report(BackendErrorPolicy::Pass), ExitCode::SUCCESS);
+        assert_eq!(err.report(BackendErrorPolicy::Fail), ExitCode::from(5));
+        assert_eq!(
+            Error::Model(anyhow::anyhow!("missing weights")).report(BackendErrorPolicy::Pass),
+            ExitCode::from(4)
+        );
+    }
+
+    #[test]
+    fn escapes_multiline_messages() {
+        assert_eq!(escape_data("100%\nfailed\r"), "100%25%0Afailed%0D");
+    }
+}
@@ -0,0 +1,398 @@
+use std::{fmt::Write, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    bert::Embedder,
+    id::{ItemId, ItemKind},
+    paths::PathFrequencies,
+    utils::{EmbeddedItem, ScoreWeights},
+};
+
+/// Two PRs labeled as duplicates of each other or not, one per line of a JSONL dataset
+///
+/// ```
+/// use pr_dedupe::eval::LabeledPair;
+///
+/// let pair: LabeledPair = serde_json::from_str(
+///     r#"{"a": ["+ : src/a.rs\nfn a() {}\n"], "b": ["+ : src/b.rs\nfn b() {}\n"], "duplicate": false}"#,
+/// ).unwrap();
+/// assert!(!pair.duplicate);
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+pub struct LabeledPair {
+    /// content entries, as built by [`crate::build_pr_content`]
+    pub a: Vec<String>,
+    pub b: Vec<String>,
+    pub duplicate: bool,
+}
+
+pub fn load_pairs(path: &Path) -> Result<Vec<LabeledPair>> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                anyhow!(
+                    "{}:{} isn't a labeled pair | Reason {e}",
+                    path.display(),
+                    i + 1
+                )
+            })
+        })
+        .collect()
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norms =
+        a.iter().map(|a| a * a).sum::<f32>().sqrt() * b.iter().map(|b| b * b).sum::<f32>().sqrt();
+    match norms {
+        n if n > 0.0 => dot / n,
+        _ => 0.0,
+    }
+}
+
+/// Precision, recall and F1 of flagging pairs scoring at least `threshold` as duplicates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub threshold: f32,
+    pub precision: f32,
+    /// also the ROC curve's true positive rate
+    pub recall: f32,
+    /// the ROC curve's x axis
+    pub false_positive_rate: f32,
+    pub f1: f32,
+}
+
+impl Metrics {
+    /// `scored` holds each pair's score, in percentage, and whether it's a duplicate
+    pub fn at(scored: &[(f32, bool)], threshold: f32) -> Self {
+        let count = |flagged: bool, duplicate: bool| {
+            scored
+                .iter()
+                .filter(|&&(score, d)| (score >= threshold) == flagged && d == duplicate)
+                .count() as f32
+        };
+        let (tp, fp) = (count(true, true), count(true, false));
+        let (fn_, tn) = (count(false, true), count(false, false));
+
+        let ratio = |a: f32, b: f32| if b > 0.0 { a / b } else { 0.0 };
+        let precision = ratio(tp, tp + fp);
+        let recall = ratio(tp, tp + fn_);
+        Self {
+            threshold,
+            precision,
+            recall,
+            false_positive_rate: ratio(fp, fp + tn),
+            f1: ratio(2.0 * precision * recall, precision + recall),
+        }
+    }
+
+    /// The whole percentage threshold with the highest F1
+    pub fn best(scored: &[(f32, bool)]) -> Self {
+        (0..=100)
+            .map(|threshold| Self::at(scored, threshold as f32))
+            .fold(Self::at(scored, 0.0), |best, m| match m.f1 > best.f1 {
+                true => m,
+                false => best,
+            })
+    }
+}
+
+/// Area under the ROC curve, the probability a duplicate pair scores higher than a distinct
+/// one. `None` unless there's at least one pair of each
+///
+/// ```
+/// use pr_dedupe::eval::roc_auc;
+///
+/// assert_eq!(roc_auc(&[(90.0, true), (40.0, false), (60.0, false)]), Some(1.0));
+/// assert_eq!(roc_auc(&[(50.0, true), (50.0, false)]), Some(0.5));
+/// ```
+pub fn roc_auc(scored: &[(f32, bool)]) -> Option<f32> {
+    let duplicates = scored.iter().filter(|(_, d)| *d).map(|(s, _)| *s);
+    let distinct = scored
+        .iter()
+        .filter(|(_, d)| !*d)
+        .map(|(s, _)| *s)
+        .collect::<Vec<_>>();
+
+    let (mut wins, mut pairs) = (0.0, 0.0);
+    for duplicate in duplicates {
+        for &other in &distinct {
+            pairs += 1.0;
+            wins += match duplicate.total_cmp(&other) {
+                std::cmp::Ordering::Greater => 1.0,
+                std::cmp::Ordering::Equal => 0.5,
+                std::cmp::Ordering::Less => 0.0,
+            };
+        }
+    }
+    (pairs > 0.0).then(|| wins / pairs)
+}
+
+/// How well one embedding model separates the labeled duplicates from the rest
+#[derive(Debug, Clone)]
+pub struct ModelReport {
+    pub model_id: String,
+    pub auc: Option<f32>,
+    pub best: Metrics,
+    /// mean similarity of duplicate pairs, in percentage on `-m`'s scale
+    pub mean_duplicate: f32,
+    /// mean similarity of distinct pairs, in percentage on `-m`'s scale
+    pub mean_distinct: f32,
+}
+
+impl ModelReport {
+    /// Embeds both sides of every pair and scores them by cosine similarity, on the scale the
+    /// vector db reports so the best threshold can be used as `-m`
+    pub async fn new(embedder: &Embedder, pairs: &[LabeledPair]) -> Result<Self> {
+        let start = std::time::Instant::now();
+        let embeddings = embed_pairs(embedder, pairs).await?;
+        let scored = embeddings
+            .chunks(2)
+            .zip(pairs)
+            .map(|(ab, pair)| {
+                let score = upstash_score(cosine_similarity(&ab[0], &ab[1]));
+                (score * 100.0, pair.duplicate)
+            })
+            .collect::<Vec<_>>();
+        let model_id = embedder.version().model_id;
+        info!(
+            "scored {} pairs with {model_id} in {:?}",
+            pairs.len(),
+            start.elapsed()
+        );
+
+        let mean = |duplicate: bool| {
+            let scores = scored
+                .iter()
+                .filter(|(_, d)| *d == duplicate)
+                .map(|(s, _)| *s);
+            let count = scores.clone().count();
+            scores.sum::<f32>() / count.max(1) as f32
+        };
+        Ok(Self {
+            model_id,
+            auc: roc_auc(&scored),
+            best: Metrics::best(&scored),
+            mean_duplicate: mean(true),
+            mean_distinct: mean(false),
+        })
+    }
+}
+
+/// How the whole pipeline, from content to the blended score a query reports, does on a
+/// labeled dataset
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    pub pairs: usize,
+    pub duplicates: usize,
+    pub auc: Option<f32>,
+    pub best: Metrics,
+    /// one row per threshold, the ROC curve's points
+    pub thresholds: Vec<Metrics>,
+}
+
+impl EvalReport {
+    /// Scores every pair the way [`crate::VectorDB::query`] scores a match, evaluating
+    /// thresholds from 0 to 100 every `step` percent
+    pub async fn new(
+        embedder: &Embedder,
+        pairs: &[LabeledPair],
+        weights: &ScoreWeights,
+        step: u8,
+    ) -> Result<Self> {
+        let start = std::time::Instant::now();
+        let mut embeddings = embed_pairs(embedder, pairs).await?.into_iter();
+        let mut items = Vec::with_capacity(pairs.len());
+        for (i, pair) in pairs.iter().enumerate() {
+            let mut item = |content: &[String], number: u64| {
+                let id = ItemId::new("github.com", "eval/pairs", ItemKind::Pull, number)?;
+                let embedding = embeddings.next().ok_or(anyhow!("missing embedding"))?;
+                Ok::<_, anyhow::Error>(EmbeddedItem::new(id, content, embedding))
+            };
+            items.push((
+                item(&pair.a, 2 * i as u64 + 1)?,
+                item(&pair.b, 2 * i as u64 + 2)?,
+            ));
+        }
+
+        let path_frequencies = PathFrequencies::new(
+            items
+                .iter()
+                .flat_map(|(a, b)| [&a.paths, &b.paths])
+                .filter_map(Option::as_ref),
+        );
+        let mut scored = Vec::with_capacity(pairs.len());
+        for ((a, b), pair) in items.iter().zip(pairs) {
+            let score = a.score(
+                upstash_score(cosine_similarity(&a.embedding, &b.embedding)),
+                b.fingerprint.as_ref(),
+                b.lexical.as_ref(),
+                b.paths.as_ref(),
+                &path_frequencies,
+                weights,
+            );
+            scored.push((score.percentage, pair.duplicate));
+        }
+        info!("scored {} pairs in {:?}", pairs.len(), start.elapsed());
+
+        Ok(Self {
+            pairs: pairs.len(),
+            duplicates: pairs.iter().filter(|p| p.duplicate).count(),
+            auc: roc_auc(&scored),
+            best: Metrics::best(&scored),
+            thresholds: (0..=100)
+                .step_by(step.max(1) as usize)
+                .map(|threshold| Metrics::at(&scored, threshold as f32))
+                .collect(),
+        })
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut report = format!(
+            "{} pairs, {} duplicates | ROC AUC {} | best F1 {:.3} at {}%\n\n\
+             | threshold | precision | recall (TPR) | FPR | F1 |\n\
+             |---|---|---|---|---|\n",
+            self.pairs,
+            self.duplicates,
+            self.auc.map_or("-".to_string(), |auc| format!("{auc:.3}")),
+            self.best.f1,
+            self.best.threshold,
+        );
+        for m in &self.thresholds {
+            let _ = writeln!(
+                report,
+                "| {} | {:.3} | {:.3} | {:.3} | {:.3} |",
+                m.threshold, m.precision, m.recall, m.false_positive_rate, m.f1
+            );
+        }
+        report
+    }
+}
+
+/// How close a quantized model's vectors are to the F32 ones, and how much faster it runs
+#[derive(Debug, Clone)]
+pub struct PrecisionReport {
+    pub prompts: usize,
+    pub min_cosine: f32,
+    pub mean_cosine: f32,
+    pub f32_time: std::time::Duration,
+    pub quantized_time: std::time::Duration,
+}
+
+impl PrecisionReport {
+    /// Embeds both sides of every pair with both models, loading included in their timings
+    pub async fn new(f32: &Embedder, quantized: &Embedder, pairs: &[LabeledPair]) -> Result<Self> {
+        let start = std::time::Instant::now();
+        let expected = embed_pairs(f32, pairs).await?;
+        let f32_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let actual = embed_pairs(quantized, pairs).await?;
+        let quantized_time = start.elapsed();
+
+        let cosines = expected
+            .iter()
+            .zip(&actual)
+            .map(|(e, a)| cosine_similarity(e, a))
+            .collect::<Vec<_>>();
+        Ok(Self {
+            prompts: cosines.len(),
+            min_cosine: cosines.iter().copied().fold(1.0, f32::min),
+            mean_cosine: cosines.iter().sum::<f32>() / cosines.len().max(1) as f32,
+            f32_time,
+            quantized_time,
+        })
+    }
+
+    /// Whether every quantized vector is within `tolerance` of its F32 counterpart
+    pub fn within(&self, tolerance: f32) -> bool {
+        self.min_cosine >= 1.0 - tolerance
+    }
+
+    pub fn markdown(&self) -> String {
+        format!(
+            "| prompts | min cosine | mean cosine | F32 | quantized |\n\
+             |---|---|---|---|---|\n\
+             | {} | {:.4} | {:.4} | {:?} | {:?} |\n",
+            self.prompts, self.min_cosine, self.mean_cosine, self.f32_time, self.quantized_time
+        )
+    }
+}
+
+/// Embeds every pair's `a` then `b` side, in one batch
+async fn embed_pairs(embedder: &Embedder, pairs: &[LabeledPair]) -> Result<Vec<Vec<f32>>> {
+    let contents = pairs
+        .iter()
+        .flat_map(|pair| [pair.a.clone(), pair.b.clone()])
+        .collect::<Vec<_>>();
+    embedder.embed_batch(&contents).await
+}
+
+/// The score Upstash's cosine metric returns, from 0 to 1
+pub(crate) fn upstash_score(cosine: f32) -> f32 {
+    (1.0 + cosine) / 2.0
+}
+
+/// Renders the model comparison as a markdown table
+pub fn reports_markdown(reports: &[ModelReport]) -> String {
+    let mut table = String::from(
+        "| model | ROC AUC | best threshold | precision | recall | F1 | mean duplicate | mean distinct |\n\
+         |---|---|---|---|---|---|---|---|\n",
+    );
+    for r in reports {
+        let auc = r.auc.map_or("-".to_string(), |auc| format!("{auc:.3}"));
+        let _ = writeln!(
+            table,
+            "| {} | {auc} | {} | {:.3} | {:.3} | {:.3} | {:.1} | {:.1} |",
+            r.model_id,
+            r.best.threshold,
+            r.best.precision,
+            r.best.recall,
+            r.best.f1,
+            r.mean_duplicate,
+            r.mean_distinct
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_threshold_with_the_best_f1() {
+        let scored = [
+            (95.0, true),
+            (85.0, true),
+            (82.0, false),
+            (60.0, false),
+            (40.0, true),
+        ];
+
+        let best = Metrics::best(&scored);
+
+        assert_eq!(best.threshold, 83.0);
+        assert_eq!(best.precision, 1.0);
+        assert!((best.recall - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(Metrics::at(&scored, 101.0).f1, 0.0);
+    }
+
+    #[test]
+    fn roc_points_go_from_everything_flagged_to_nothing() {
+        let scored = [(90.0, true), (70.0, false), (30.0, false)];
+
+        let all = Metrics::at(&scored, 0.0);
+        let none = Metrics::at(&scored, 100.0);
+
+        assert_eq!((all.recall, all.false_positive_rate), (1.0, 1.0));
+        assert_eq!((none.recall, none.false_positive_rate), (0.0, 0.0));
+        assert_eq!(Metrics::at(&scored, 80.0).false_positive_rate, 0.0);
+    }
+}
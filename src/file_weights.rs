@@ -0,0 +1,200 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Error};
+
+/// What a touched file is, guessed from its path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Source,
+    /// tests and their fixtures
+    Test,
+    Docs,
+    Config,
+}
+
+impl FileKind {
+    /// ```
+    /// use pr_dedupe::file_weights::FileKind;
+    ///
+    /// assert_eq!(FileKind::of("src/lib.rs"), FileKind::Source);
+    /// assert_eq!(FileKind::of("tests/fixtures/pr.json"), FileKind::Test);
+    /// assert_eq!(FileKind::of("src/parser_test.go"), FileKind::Test);
+    /// assert_eq!(FileKind::of("docs/setup.md"), FileKind::Docs);
+    /// assert_eq!(FileKind::of(".github/workflows/ci.yml"), FileKind::Config);
+    /// ```
+    pub fn of(path: &str) -> Self {
+        let path = path.to_lowercase();
+        let (dirs, name) = path.rsplit_once('/').unwrap_or(("", &path));
+        let dirs = dirs.split('/').collect::<Vec<_>>();
+        let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+
+        let test_dir = |dir: &&str| {
+            matches!(
+                *dir,
+                "test" | "tests" | "__tests__" | "spec" | "fixtures" | "testdata" | "snapshots"
+            )
+        };
+        if dirs.iter().any(test_dir)
+            || stem.starts_with("test_")
+            || stem.ends_with("_test")
+            || stem.ends_with(".test")
+            || stem.ends_with(".spec")
+            || extension == "snap"
+        {
+            return FileKind::Test;
+        }
+        if dirs.iter().any(|dir| matches!(*dir, "doc" | "docs"))
+            || matches!(extension, "md" | "mdx" | "rst" | "adoc" | "txt")
+            || matches!(stem, "license" | "changelog" | "authors")
+        {
+            return FileKind::Docs;
+        }
+        if name.starts_with('.')
+            || matches!(
+                extension,
+                "json" | "yml" | "yaml" | "toml" | "ini" | "cfg" | "conf" | "lock" | "xml" | "env"
+            )
+            || matches!(stem, "dockerfile" | "makefile")
+        {
+            return FileKind::Config;
+        }
+        FileKind::Source
+    }
+}
+
+/// How much each touched file counts in a PR's vector, by path pattern then by kind. A
+/// weight of 0 leaves the file out. Parsed from comma separated `key=weight` pairs, keys
+/// being a kind (`source`, `test`, `docs`, `config`) or a glob where `*` matches within a
+/// directory and `**` across them. Patterns are checked in order, before kinds
+///
+/// ```
+/// use pr_dedupe::file_weights::FileWeights;
+///
+/// let weights: FileWeights = "test=0.1,vendor/**=0".parse().unwrap();
+/// assert_eq!(weights.weight(Some("vendor/lib/a.c")), 0.0);
+/// assert_eq!(weights.weight(Some("tests/a.rs")), 0.1);
+/// assert_eq!(weights.weight(Some("src/a.rs")), 1.0);
+/// // issues and other content that isn't a file
+/// assert_eq!(weights.weight(None), 1.0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileWeights {
+    pub source: f32,
+    pub test: f32,
+    pub docs: f32,
+    pub config: f32,
+    pub patterns: Vec<(String, f32)>,
+}
+
+impl Default for FileWeights {
+    fn default() -> Self {
+        Self {
+            source: 1.0,
+            test: 0.3,
+            docs: 0.5,
+            config: 0.5,
+            patterns: vec![],
+        }
+    }
+}
+
+impl FileWeights {
+    /// Weight of the file at `path`, content that isn't a file always weighs 1
+    pub fn weight(&self, path: Option<&str>) -> f32 {
+        let Some(path) = path else {
+            return 1.0;
+        };
+        if let Some((_, weight)) = self.patterns.iter().find(|(p, _)| glob_match(p, path)) {
+            return *weight;
+        }
+        match FileKind::of(path) {
+            FileKind::Source => self.source,
+            FileKind::Test => self.test,
+            FileKind::Docs => self.docs,
+            FileKind::Config => self.config,
+        }
+    }
+}
+
+impl FromStr for FileWeights {
+    type Err = Error;
+
+    /// Overrides the default weights, an empty string keeps them all
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, weight) = pair
+                .rsplit_once('=')
+                .ok_or(anyhow!("file weight '{pair}' isn't a key=weight pair"))?;
+            let weight = weight
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|w| *w >= 0.0)
+                .ok_or(anyhow!("file weight '{pair}' must be a positive number"))?;
+            match key.trim() {
+                "source" => weights.source = weight,
+                "test" => weights.test = weight,
+                "docs" => weights.docs = weight,
+                "config" => weights.config = weight,
+                pattern => weights.patterns.push((pattern.to_string(), weight)),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+impl Display for FileWeights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "source={},test={},docs={},config={}",
+            self.source, self.test, self.docs, self.config
+        )?;
+        self.patterns
+            .iter()
+            .try_for_each(|(pattern, weight)| write!(f, ",{pattern}={weight}"))
+    }
+}
+
+/// Whether `path` matches `pattern`, `*` matching anything but `/` and `**` anything
+fn glob_match(pattern: &str, path: &str) -> bool {
+    match pattern.strip_prefix("**") {
+        Some(rest) => {
+            // `**/` may also match no directory at all
+            let rest_without_slash = rest.strip_prefix('/').unwrap_or(rest);
+            (0..=path.len())
+                .filter(|&i| path.is_char_boundary(i))
+                .any(|i| glob_match(rest, &path[i..]) || glob_match(rest_without_slash, &path[i..]))
+        }
+        None => match pattern.strip_prefix('*') {
+            Some(rest) => (0..=path.len())
+                .filter(|&i| path.is_char_boundary(i) && !path[..i].contains('/'))
+                .any(|i| glob_match(rest, &path[i..])),
+            None => match (pattern.chars().next(), path.chars().next()) {
+                (None, None) => true,
+                (Some(p), Some(c)) if p == c => {
+                    glob_match(&pattern[p.len_utf8()..], &path[c.len_utf8()..])
+                }
+                _ => false,
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_within_and_across_directories() {
+        assert!(glob_match("*.lock", "Cargo.lock"));
+        assert!(!glob_match("*.lock", "web/yarn.lock"));
+        assert!(glob_match("**/*.lock", "web/yarn.lock"));
+        assert!(glob_match("**/*.lock", "Cargo.lock"));
+        assert!(glob_match("vendor/**", "vendor/a/b.c"));
+        assert!(!glob_match("vendor/**", "src/vendor.rs"));
+        assert!(glob_match("src/*/mod.rs", "src/a/mod.rs"));
+        assert!(!glob_match("src/*/mod.rs", "src/a/b/mod.rs"));
+    }
+}
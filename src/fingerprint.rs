@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// hex chars kept of each file's hash, enough to tell a PR's files apart while keeping
+/// metadata of PRs touching hundreds of files small
+const FILE_HASH_LEN: usize = 16;
+
+/// Hashes of a PR's normalized content. Byte-identical changes (up to line endings, trailing
+/// whitespace and blank lines) get the same hashes, wherever and whenever they were opened
+///
+/// ```
+/// use pr_dedupe::fingerprint::Fingerprint;
+///
+/// let upstream = ["+ : https://github.com/acme/app/raw/abc/src/lib.rs\nfn a() {}\n".to_string()];
+/// let fork = ["+ : https://github.com/fork/app/raw/def/src/lib.rs\r\nfn a() {}  \r\n\r\n".to_string()];
+///
+/// assert_eq!(Fingerprint::new(&upstream), Fingerprint::new(&fork));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// hash of the whole change, independent of the order files were listed in
+    pub content_hash: String,
+    /// truncated hash of each touched file, by path
+    pub file_hashes: BTreeMap<String, String>,
+}
+
+impl Fingerprint {
+    /// Fingerprints the content built by [`crate::build_pr_content`] or
+    /// [`crate::build_issue_content`], `None` when there's nothing to hash, e.g. for PRs
+    /// without files, which would otherwise all be exact duplicates of each other
+    pub fn new(content: &[String]) -> Option<Self> {
+        let mut entries = content
+            .iter()
+            .map(|entry| normalize(entry))
+            .filter(|(path, body)| path.is_some() || !body.is_empty())
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return None;
+        }
+        entries.sort();
+
+        let mut content_hash = Sha256::new();
+        let mut file_hashes = BTreeMap::new();
+        for (path, body) in &entries {
+            let hash = hex(&Sha256::digest(body.as_bytes()));
+            content_hash.update(path.as_deref().unwrap_or_default());
+            content_hash.update([0]);
+            content_hash.update(&hash);
+            content_hash.update([0]);
+            if let Some(path) = path {
+                file_hashes.insert(path.clone(), hash[..FILE_HASH_LEN].to_string());
+            }
+        }
+
+        Some(Self {
+            content_hash: hex(&content_hash.finalize()),
+            file_hashes,
+        })
+    }
+
+    /// Paths both fingerprints have identical content for
+    pub fn identical_files(&self, other: &Fingerprint) -> Vec<String> {
+        self.file_hashes
+            .iter()
+            .filter(|(path, hash)| other.file_hashes.get(*path) == Some(hash))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+/// Splits a content entry into the touched file's path, if it's a file, and its normalized
+/// content. File entries start with a `<action> : <raw url>` line, see `content::parse`
+pub(crate) fn normalize(entry: &str) -> (Option<String>, String) {
+    let entry = entry.replace("\r\n", "\n");
+    let (header, body) = entry.split_once('\n').unwrap_or((&entry, ""));
+
+    let file = header
+        .split_once(" : ")
+        .filter(|(action, _)| matches!(*action, "+" | "M" | "-" | "^"));
+
+    let lines = |text: &str| {
+        text.lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    match file {
+        // the action is part of the hash, adding a file isn't the same change as removing it
+        Some((action, url)) => (
+            Some(repo_path(url).to_string()),
+            format!("{action}\n{}", lines(body)),
+        ),
+        None => (None, lines(&entry)),
+    }
+}
+
+/// The file's path within the repo, dropping the host, repo and commit from its raw url so
+/// the same change hashes the same in forks and across pushes
+fn repo_path(url: &str) -> &str {
+    match url.split_once("/raw/") {
+        Some((_, path)) => path.split_once('/').map_or(path, |(_sha, path)| path),
+        None => url,
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(action: char, path: &str, content: &str) -> String {
+        format!("{action} : https://github.com/acme/app/raw/abc/{path}\n{content}\n")
+    }
+
+    #[test]
+    fn file_order_and_whitespace_dont_change_the_hash() {
+        let a = Fingerprint::new(&[file('+', "a.rs", "fn a() {}"), file('M', "b.rs", "x\ny")]);
+        let b = Fingerprint::new(&[
+            file('M', "b.rs", "x  \n\ny\n"),
+            file('+', "a.rs", "fn a() {}"),
+        ]);
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn tracks_identical_files() {
+        let a =
+            Fingerprint::new(&[file('+', "a.rs", "fn a() {}"), file('M', "b.rs", "x")]).unwrap();
+        let b =
+            Fingerprint::new(&[file('+', "a.rs", "fn a() {}"), file('M', "b.rs", "y")]).unwrap();
+
+        assert_ne!(a.content_hash, b.content_hash);
+        assert_eq!(a.identical_files(&b), ["a.rs"]);
+    }
+
+    #[test]
+    fn empty_prs_have_no_fingerprint() {
+        assert_eq!(Fingerprint::new(&[" ".to_string()]), None);
+    }
+}
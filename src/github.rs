@@ -0,0 +1,205 @@
+use std::{env, sync::Arc};
+
+use anyhow::{bail, Result};
+use log::warn;
+use reqwest::{header, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{content::PrFiles, http::HttpClient, id::github_host};
+
+const GITHUB_API_URL: &str = "https://api.github.com/";
+
+/// Max page size allowed by GitHub's REST API
+const PER_PAGE: u8 = 100;
+
+pub struct GitHub {
+    client: Arc<HttpClient>,
+    /// sent with every request, the client being shared with other apis
+    headers: header::HeaderMap,
+    api_url: Url,
+    host: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Head {
+    pub sha: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PullRequest {
+    pub number: u64,
+    pub state: String,
+    pub updated_at: String,
+    pub closed_at: Option<String>,
+    pub head: Head,
+    pub base: Head,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    /// `None` when the issue has no description
+    pub body: Option<String>,
+    pub labels: Vec<Label>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Repository {
+    pub full_name: String,
+    pub private: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PullFile {
+    filename: String,
+    status: String,
+    /// set on renamed files
+    #[serde(default)]
+    previous_filename: Option<String>,
+}
+
+impl GitHub {
+    pub fn new(client: Arc<HttpClient>) -> Result<Self> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            header::HeaderValue::from_static("application/vnd.github+json"),
+        );
+
+        match env::var("GITHUB_TOKEN") {
+            Ok(token) => {
+                let mut value = header::HeaderValue::from_str(&format!("Bearer {token}"))?;
+                value.set_sensitive(true);
+                headers.insert(header::AUTHORIZATION, value);
+            }
+            Err(_) => {
+                warn!("GITHUB_TOKEN isn't set, requests to the GitHub api will be heavily rate limited");
+            }
+        }
+
+        // github rejects requests without a user agent
+        headers.insert(
+            header::USER_AGENT,
+            header::HeaderValue::from_static(env!("CARGO_PKG_NAME")),
+        );
+
+        let api_url =
+            Url::parse(&env::var("GITHUB_API_URL").unwrap_or_else(|_| GITHUB_API_URL.to_string()))?;
+
+        Ok(Self {
+            client,
+            headers,
+            api_url,
+            host: github_host(),
+        })
+    }
+
+    /// host PRs and issues live on, github.com or a GitHub Enterprise server
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let uri = self.api_url.join(path)?;
+
+        let resp = self
+            .client
+            .send(self.client.get(uri).headers(self.headers.clone()))
+            .await?;
+
+        if resp.status().as_u16() != 200 {
+            bail!(
+                "GitHub api request to {path} failed | Reason {}",
+                resp.text().await?
+            );
+        }
+
+        Ok(serde_json::from_str::<T>(&resp.text().await?)?)
+    }
+
+    /// Lists one page of a repo's PRs, most recently updated first.
+    /// `state` is one of `open`, `closed` or `all`
+    pub async fn list_pulls(
+        &self,
+        repo_name: &str,
+        state: &str,
+        page: u32,
+    ) -> Result<Vec<PullRequest>> {
+        self.get(&format!(
+            "repos/{repo_name}/pulls?state={state}&sort=updated&direction=desc&per_page={PER_PAGE}&page={page}"
+        ))
+        .await
+    }
+
+    /// The repo, or `None` when it doesn't exist or the token can't see it
+    pub async fn repo(&self, repo_name: &str) -> Result<Option<Repository>> {
+        let uri = self.api_url.join(&format!("repos/{repo_name}"))?;
+
+        let resp = self
+            .client
+            .send(self.client.get(uri).headers(self.headers.clone()))
+            .await?;
+
+        match resp.status().as_u16() {
+            200 => Ok(Some(serde_json::from_str(&resp.text().await?)?)),
+            // github answers 404 rather than 403 for private repos the token can't access
+            403 | 404 => Ok(None),
+            _ => bail!(
+                "GitHub api request to repos/{repo_name} failed | Reason {}",
+                resp.text().await?
+            ),
+        }
+    }
+
+    pub async fn pull(&self, repo_name: &str, pr_number: u64) -> Result<PullRequest> {
+        self.get(&format!("repos/{repo_name}/pulls/{pr_number}"))
+            .await
+    }
+
+    pub async fn issue(&self, repo_name: &str, issue_number: u64) -> Result<Issue> {
+        self.get(&format!("repos/{repo_name}/issues/{issue_number}"))
+            .await
+    }
+
+    /// Lists every file touched by a PR, grouped the same way as the action's changed files,
+    /// with the old path of renamed files
+    pub async fn pull_files(&self, repo_name: &str, pr_number: u64) -> Result<PrFiles> {
+        let mut files = PrFiles::default();
+        let mut page = 1;
+
+        loop {
+            let pull_files = self
+                .get::<Vec<PullFile>>(&format!(
+                    "repos/{repo_name}/pulls/{pr_number}/files?per_page={PER_PAGE}&page={page}"
+                ))
+                .await?;
+            let is_last_page = pull_files.len() < PER_PAGE as usize;
+
+            for file in pull_files {
+                match file.status.as_str() {
+                    "added" | "copied" => files.added.push(file.filename),
+                    "modified" | "changed" => files.modified.push(file.filename),
+                    "removed" => files.removed.push(file.filename),
+                    "renamed" => {
+                        if let Some(previous) = file.previous_filename {
+                            files.renamed_from.insert(file.filename.clone(), previous);
+                        }
+                        files.renamed.push(file.filename)
+                    }
+                    _ => {}
+                }
+            }
+
+            if is_last_page {
+                return Ok(files);
+            }
+            page += 1;
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use rand::Rng;
+use reqwest::{header, Client, ClientBuilder, RequestBuilder, Response, StatusCode};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// retries after the first attempt
+    pub max_retries: u32,
+    /// backoff before the first retry, doubled on every following retry
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// upper bound on a server's `Retry-After`, so a misbehaving server can't stall the action
+    pub max_retry_after: Duration,
+    /// timeout of a single attempt
+    pub timeout: Duration,
+    pub max_concurrency_per_host: usize,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+            timeout: Duration::from_secs(30),
+            max_concurrency_per_host: 8,
+        }
+    }
+}
+
+/// reqwest client shared by the GitHub and vector db clients and file downloads, built once
+/// per process so the per host caps hold across all of them.
+/// Retries 429s, 5xxs and connection errors with jittered exponential backoff and caps the
+/// number of in-flight requests per host
+pub struct HttpClient {
+    client: Client,
+    config: RetryConfig,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl Deref for HttpClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl HttpClient {
+    pub fn new(builder: ClientBuilder) -> Result<Self> {
+        Self::with_config(builder, RetryConfig::default())
+    }
+
+    pub fn with_config(builder: ClientBuilder, config: RetryConfig) -> Result<Self> {
+        Ok(Self {
+            client: builder.timeout(config.timeout).build()?,
+            config,
+            hosts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn host_limit(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.config.max_concurrency_per_host)))
+            .clone()
+    }
+
+    /// Sends the request, retrying it while it's retryable and retries are left.
+    /// The last response is returned as is, so callers still decide what a failure means
+    pub async fn send(&self, request: RequestBuilder) -> Result<HttpResponse> {
+        let request = request.build()?;
+        let host_limit = self.host_limit(request.url().host_str().unwrap_or_default());
+
+        let mut attempt = 0;
+        loop {
+            let attempt_request = request
+                .try_clone()
+                .ok_or_else(|| anyhow!("Couldn't retry request to {}", request.url()))?;
+
+            let permit = host_limit.clone().acquire_owned().await?;
+            let result = self.client.execute(attempt_request).await;
+
+            let delay = match &result {
+                Ok(resp) if is_retryable(resp.status()) => retry_after(resp)
+                    .map(|d| d.min(self.config.max_retry_after))
+                    .unwrap_or_else(|| self.backoff(attempt)),
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    self.backoff(attempt)
+                }
+                _ => return Ok(HttpResponse::new(result?, permit)),
+            };
+
+            if attempt >= self.config.max_retries {
+                return Ok(HttpResponse::new(result?, permit));
+            }
+            // other requests to the host can go while this one backs off
+            drop(permit);
+
+            match &result {
+                Ok(resp) => warn!(
+                    "{} {} returned {}, retrying in {delay:?}",
+                    request.method(),
+                    request.url(),
+                    resp.status()
+                ),
+                Err(e) => warn!(
+                    "{} {} failed, retrying in {delay:?} | Reason {e}",
+                    request.method(),
+                    request.url()
+                ),
+            }
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// exponential backoff with full jitter
+    fn backoff(&self, attempt: u32) -> Duration {
+        let max = self
+            .config
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.config.max_delay);
+        max.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Response holding its host's permit until its body is read or it's dropped, so the per
+/// host cap also covers the body's transfer. Read it before waiting on other requests to the
+/// same host, or they may wait on its permit
+pub struct HttpResponse {
+    response: Response,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for HttpResponse {
+    type Target = Response;
+
+    fn deref(&self) -> &Self::Target {
+        &self.response
+    }
+}
+
+impl HttpResponse {
+    fn new(response: Response, permit: OwnedSemaphorePermit) -> Self {
+        Self {
+            response,
+            _permit: permit,
+        }
+    }
+
+    pub async fn text(self) -> reqwest::Result<String> {
+        self.response.text().await
+    }
+
+    pub async fn bytes(self) -> reqwest::Result<Vec<u8>> {
+        Ok(self.response.bytes().await?.to_vec())
+    }
+
+    pub fn error_for_status(self) -> reqwest::Result<Self> {
+        self.response.error_for_status_ref()?;
+        Ok(self)
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// `Retry-After` in seconds. The http-date form isn't supported and falls back to backoff
+fn retry_after(resp: &Response) -> Option<Duration> {
+    resp.headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockStats {
+        requests: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    /// Serves `responses` in order, one per connection, then repeats the last one.
+    /// Every response is held for `latency` to make concurrent requests overlap
+    async fn mock_server(
+        responses: Vec<&'static str>,
+        latency: Duration,
+    ) -> (String, Arc<MockStats>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let stats = Arc::new(MockStats::default());
+
+        let server_stats = stats.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let i = server_stats.requests.fetch_add(1, Ordering::SeqCst);
+                let response = responses[i.min(responses.len() - 1)];
+                let stats = server_stats.clone();
+
+                tokio::spawn(async move {
+                    let in_flight = stats.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    stats.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+                    // requests in these tests have no body, so the headers are all there is
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let n = socket.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            break;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                    }
+
+                    tokio::time::sleep(latency).await;
+                    stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                    socket.shutdown().await.unwrap();
+                });
+            }
+        });
+
+        (url, stats)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    fn test_client(config: RetryConfig) -> HttpClient {
+        HttpClient::with_config(Client::builder(), config).unwrap()
+    }
+
+    fn fast_retries() -> RetryConfig {
+        RetryConfig {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_until_success() {
+        let (url, stats) = mock_server(vec![UNAVAILABLE, UNAVAILABLE, OK], Duration::ZERO).await;
+        let client = test_client(fast_retries());
+
+        let resp = client.send(client.get(&url)).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), "ok");
+        assert_eq!(stats.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (url, stats) = mock_server(vec![UNAVAILABLE], Duration::ZERO).await;
+        let client = test_client(RetryConfig {
+            max_retries: 2,
+            ..fast_retries()
+        });
+
+        let resp = client.send(client.get(&url)).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(stats.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn honors_retry_after_over_backoff() {
+        let (url, stats) = mock_server(vec![RATE_LIMITED, OK], Duration::ZERO).await;
+        // backoff alone would make this test take a minute
+        let client = test_client(RetryConfig {
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        let start = std::time::Instant::now();
+        let resp = client.send(client.get(&url)).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(stats.requests.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, stats) = mock_server(vec![NOT_FOUND, OK], Duration::ZERO).await;
+        let client = test_client(fast_retries());
+
+        let resp = client.send(client.get(&url)).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(stats.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_requests_per_host() {
+        let (url, stats) = mock_server(vec![OK], Duration::from_millis(50)).await;
+        let client = test_client(RetryConfig {
+            max_concurrency_per_host: 2,
+            ..fast_retries()
+        });
+
+        // a response holds its permit until its body is read
+        let bodies = futures::future::join_all(
+            (0..6).map(|_| async { client.send(client.get(&url)).await.unwrap().text().await }),
+        )
+        .await;
+
+        assert!(bodies.iter().all(|b| b.as_ref().unwrap() == "ok"));
+        assert_eq!(stats.requests.load(Ordering::SeqCst), 6);
+        assert_eq!(stats.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+}
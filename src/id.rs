@@ -0,0 +1,291 @@
+use std::{env, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+pub const GITHUB_HOST: &str = "github.com";
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum IdError {
+    #[error("{0:?} isn't an owner/name repo")]
+    InvalidRepo(String),
+    #[error("{0:?} isn't a valid PR or issue number")]
+    InvalidNumber(String),
+    #[error("{0:?} isn't a known kind, expected 'pull' or 'issues'")]
+    InvalidKind(String),
+    #[error("{0:?} isn't a valid id, expected host/owner/repo/kind/number")]
+    Malformed(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ItemKind {
+    Pull,
+    Issue,
+}
+
+impl ItemKind {
+    /// the kind's segment in GitHub urls
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemKind::Pull => "pull",
+            ItemKind::Issue => "issues",
+        }
+    }
+}
+
+impl FromStr for ItemKind {
+    type Err = IdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pull" => Ok(ItemKind::Pull),
+            "issues" => Ok(ItemKind::Issue),
+            _ => Err(IdError::InvalidKind(s.to_string())),
+        }
+    }
+}
+
+/// Identifies a PR or issue on github.com or a GitHub Enterprise host.
+///
+/// Stored in the vector db as `host/owner/repo/kind/number`, mirroring the item's url, e.g.
+/// `github.com/cs50victor/pr_dedupe/pull/2`. Ids written before this format existed look
+/// like `owner/repo:number` and are still understood by [`ItemId::from_stored`]
+///
+/// ```
+/// use pr_dedupe::id::{ItemId, ItemKind};
+///
+/// let id = ItemId::new("github.com", "cs50victor/pr_dedupe", ItemKind::Pull, 2).unwrap();
+/// assert_eq!(id.to_string(), "github.com/cs50victor/pr_dedupe/pull/2");
+/// assert_eq!(id.to_string().parse::<ItemId>().unwrap(), id);
+/// assert_eq!(ItemId::from_stored("cs50victor/pr_dedupe:2").unwrap(), id);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ItemId {
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
+    pub kind: ItemKind,
+    pub number: u64,
+}
+
+impl ItemId {
+    /// `repo_name` is owner/name
+    pub fn new(host: &str, repo_name: &str, kind: ItemKind, number: u64) -> Result<Self, IdError> {
+        let (owner, repo) = split_repo_name(repo_name)?;
+        Ok(Self {
+            host: host.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            kind,
+            number,
+        })
+    }
+
+    /// owner/name
+    pub fn repo_name(&self) -> String {
+        format!("{}/{}", self.owner, self.repo)
+    }
+
+    pub fn same_repo(&self, other: &ItemId) -> bool {
+        self.host == other.host && self.owner == other.owner && self.repo == other.repo
+    }
+
+    pub fn url(&self) -> String {
+        format!(
+            "https://{}/{}/{}/{}/{}",
+            self.host,
+            self.owner,
+            self.repo,
+            self.kind.as_str(),
+            self.number
+        )
+    }
+
+    /// Parses the item's `https://host/owner/repo/kind/number` url
+    pub fn from_url(url: &str) -> Result<Self, IdError> {
+        url.strip_prefix("https://")
+            .ok_or_else(|| IdError::Malformed(url.to_string()))?
+            .parse()
+    }
+
+    /// Parses an id read from the vector db, accepting both the current and legacy formats
+    pub fn from_stored(id: &str) -> Result<Self, IdError> {
+        match is_legacy(id) {
+            true => Self::from_legacy(id),
+            false => id.parse(),
+        }
+    }
+
+    /// Parses a legacy `owner/repo:number` id. Those were only ever written for github.com PRs
+    pub fn from_legacy(id: &str) -> Result<Self, IdError> {
+        let (repo_name, number) = id
+            .rsplit_once(':')
+            .ok_or_else(|| IdError::Malformed(id.to_string()))?;
+        let number = number
+            .parse()
+            .map_err(|_| IdError::InvalidNumber(id.to_string()))?;
+        Self::new(GITHUB_HOST, repo_name, ItemKind::Pull, number)
+    }
+
+    /// The legacy id this item would have been stored under, if it had one
+    pub fn legacy_id(&self) -> Option<String> {
+        match (self.host.as_str(), self.kind) {
+            (GITHUB_HOST, ItemKind::Pull) => Some(format!("{}:{}", self.repo_name(), self.number)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `id` uses the legacy `owner/repo:number` format. Current ids may hold a `:` too,
+/// in a GitHub Enterprise host's port
+pub fn is_legacy(id: &str) -> bool {
+    id.rsplit_once(':').is_some_and(|(repo_name, number)| {
+        repo_name.matches('/').count() == 1
+            && !number.is_empty()
+            && number.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// The GitHub host the action runs against, `github.com` unless running on GitHub Enterprise
+pub fn github_host() -> String {
+    env::var("GITHUB_SERVER_URL")
+        .ok()
+        .and_then(|url| {
+            url.trim_end_matches('/')
+                .rsplit("://")
+                .next()
+                .map(String::from)
+        })
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| GITHUB_HOST.to_string())
+}
+
+fn split_repo_name(repo_name: &str) -> Result<(&str, &str), IdError> {
+    match repo_name.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Ok((owner, repo))
+        }
+        _ => Err(IdError::InvalidRepo(repo_name.to_string())),
+    }
+}
+
+impl Display for ItemId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}/{}",
+            self.host,
+            self.owner,
+            self.repo,
+            self.kind.as_str(),
+            self.number
+        )
+    }
+}
+
+impl FromStr for ItemId {
+    type Err = IdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split('/').collect::<Vec<_>>();
+        let [host, owner, repo, kind, number] = parts[..] else {
+            return Err(IdError::Malformed(s.to_string()));
+        };
+        if [host, owner, repo].iter().any(|part| part.is_empty()) {
+            return Err(IdError::Malformed(s.to_string()));
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            kind: kind.parse()?,
+            number: number
+                .parse()
+                .map_err(|_| IdError::InvalidNumber(s.to_string()))?,
+        })
+    }
+}
+
+impl TryFrom<String> for ItemId {
+    type Error = IdError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ItemId> for String {
+    fn from(val: ItemId) -> Self {
+        val.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_id_decodes_pr_number() {
+        let id = ItemId::from_stored("cs50victor/pr_dedupe:2").unwrap();
+
+        assert_eq!(id.number, 2);
+        assert_eq!(id.kind, ItemKind::Pull);
+        assert_eq!(id.legacy_id().unwrap(), "cs50victor/pr_dedupe:2");
+    }
+
+    #[test]
+    fn legacy_id_decodes_repo_name() {
+        let id = ItemId::from_stored("cs50victor/pr_dedupe:2").unwrap();
+
+        assert_eq!(id.host, GITHUB_HOST);
+        assert_eq!(id.repo_name(), "cs50victor/pr_dedupe");
+    }
+
+    #[test]
+    fn enterprise_issue_roundtrips() {
+        let id = ItemId::new("ghe.example.com", "org/app", ItemKind::Issue, 41).unwrap();
+
+        assert_eq!(id.to_string(), "ghe.example.com/org/app/issues/41");
+        assert_eq!(id.url(), "https://ghe.example.com/org/app/issues/41");
+        assert_eq!(ItemId::from_url(&id.url()).unwrap(), id);
+        assert_eq!(ItemId::from_stored(&id.to_string()).unwrap(), id);
+        assert_eq!(id.legacy_id(), None);
+        assert_eq!(
+            serde_json::to_string(&id).unwrap(),
+            "\"ghe.example.com/org/app/issues/41\""
+        );
+    }
+
+    #[test]
+    fn hosts_with_a_port_arent_legacy() {
+        let id = ItemId::new("ghe.corp:8443", "acme/app", ItemKind::Pull, 7).unwrap();
+
+        assert!(!is_legacy("ghe.corp:8443/acme/app/pull/7"));
+        assert!(is_legacy("acme/app:7"));
+        assert_eq!(
+            ItemId::from_stored("ghe.corp:8443/acme/app/pull/7").unwrap(),
+            id
+        );
+    }
+
+    #[test]
+    fn invalid_ids_are_errors() {
+        assert_eq!(
+            ItemId::from_stored("cs50victor/pr_dedupe:two"),
+            Err(IdError::Malformed("cs50victor/pr_dedupe:two".into()))
+        );
+        assert_eq!(
+            ItemId::from_stored("github.com/a/b/commits/1"),
+            Err(IdError::InvalidKind("commits".into()))
+        );
+        assert_eq!(
+            ItemId::from_stored("github.com/a/pull/1"),
+            Err(IdError::Malformed("github.com/a/pull/1".into()))
+        );
+        assert_eq!(
+            ItemId::new(GITHUB_HOST, "pr_dedupe", ItemKind::Pull, 1),
+            Err(IdError::InvalidRepo("pr_dedupe".into()))
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Finds duplicate or similar pull requests by embedding their content with a
+//! sentence-transformers model and querying a vector database for close neighbours.
+//!
+//! The `pr_dedupe` binary is a thin cli over this crate; the pieces it's built from can
+//! be reused on their own:
+//!
+//! ```no_run
+//! use pr_dedupe::{
+//!     bert,
+//!     id::ItemKind,
+//!     utils::{EmbeddedItem, QueryOptions},
+//!     Bert, ItemId, VectorDB,
+//! };
+//! use pr_dedupe::{http::HttpClient, upstash::Upstash};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let content = vec!["+ : src/lib.rs\npub fn add(a: u8, b: u8) -> u8 { a + b }\n".to_string()];
+//! let embedding = bert::generate_embeddings(content.clone(), 384).await?;
+//! let pr = ItemId::new("github.com", "cs50victor/pr_dedupe", ItemKind::Pull, 2)?;
+//!
+//! let http = Arc::new(HttpClient::new(reqwest::Client::builder())?);
+//! let vector_db = Upstash::new(http)?;
+//! let similar_prs = vector_db
+//!     .query(
+//!         &EmbeddedItem::new(pr, &content, embedding),
+//!         &QueryOptions::new(ItemKind::Pull, 10, 80),
+//!         &Bert::new().version(),
+//!     )
+//!     .await?;
+//! println!("{}", similar_prs.to_html_table());
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod backfill;
+pub mod bert;
+pub mod calibrate;
+pub mod content;
+pub mod embedding_cache;
+pub mod encoder;
+pub mod error;
+pub mod eval;
+pub mod file_weights;
+mod files_to_ignore;
+pub mod fingerprint;
+pub mod github;
+pub mod http;
+pub mod id;
+pub mod links;
+pub mod migrate;
+pub mod minhash;
+pub mod model;
+pub mod paths;
+pub mod reindex;
+pub mod rerank;
+pub mod scope;
+mod supabase;
+pub mod upstash;
+pub mod utils;
+
+use serde::{Deserialize, Serialize};
+
+pub use bert::{Bert, Embedding, EmbeddingResponse};
+pub use content::{build_issue_content, build_pr_content, PrFiles};
+pub use id::ItemId;
+pub use utils::VectorDB;
+
+/// A stored PR or issue that matched the current one
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SimilarPRsInner {
+    pub pr_url: String,
+    pub percentage: f32,
+    /// owner/name of the match's repo, when it isn't the queried PR's repo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// a near-exact copy of the queried PR in a related repo, e.g. the same patch sent to a fork
+    #[serde(default)]
+    pub same_patch: bool,
+    /// same content hash as the queried PR, not just semantically similar
+    #[serde(default)]
+    pub exact: bool,
+    /// files changed identically in both PRs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identical_files: Vec<String>,
+    /// estimated token overlap, in percent, when both PRs have a lexical signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexical: Option<f32>,
+    /// touched paths' similarity, in percent, when both touch files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paths: Option<f32>,
+    /// cross-encoder score, in percent, when the match was reranked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank: Option<f32>,
+    /// the match's stored summary, what it's reranked on
+    #[serde(skip)]
+    pub summary: Option<String>,
+}
+
+/// PRs or issues similar to the current one, as returned by [`VectorDB::query`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SimilarPRs {
+    pub data: Vec<SimilarPRsInner>,
+}
+
+impl SimilarPRs {
+    /// Combines matches from several queries, keeping the `top_k` most similar
+    pub fn merge(mut self, other: SimilarPRs, top_k: u8) -> Self {
+        self.data.extend(other.data);
+        self.data
+            .sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+        self.data.truncate(top_k as usize);
+        self
+    }
+
+    /// Renders the matches as the html table posted in PR comments
+    ///
+    /// ```
+    /// use pr_dedupe::{SimilarPRs, SimilarPRsInner};
+    ///
+    /// let similar_prs = SimilarPRs {
+    ///     data: vec![SimilarPRsInner {
+    ///         pr_url: "https://github.com/cs50victor/pr_dedupe/pull/2".to_string(),
+    ///         percentage: 92.5,
+    ///         ..Default::default()
+    ///     }],
+    /// };
+    ///
+    /// assert_eq!(
+    ///     similar_prs.to_html_table(),
+    ///     "<table><tr><th>PR</th><th>Similarity</th></tr><tr><td>#2</td><td>92.5%</td></tr></table>"
+    /// );
+    /// ```
+    pub fn to_html_table(&self) -> String {
+        match self.data.is_empty() {
+            true => "".into(),
+            false => {
+                #[allow(clippy::format_collect)]
+                let table_content = self
+                    .data
+                    .iter()
+                    .map(|f| {
+                        format!(
+                            "<tr><td>{}#{}{}</td><td>{}%</td></tr>",
+                            f.repo.as_deref().unwrap_or_default(),
+                            f.pr_url.rsplit('/').next().unwrap_or_default(),
+                            match (f.exact, f.same_patch) {
+                                (_, true) => " (same patch elsewhere)",
+                                (true, false) => " (exact duplicate)",
+                                _ => "",
+                            },
+                            f.percentage
+                        )
+                    })
+                    .collect::<String>();
+
+                // self.data.iter().fold(String::from("| PR | Similarity |\n| ---   | --- |\n"), | mut output, b| {
+                //     let (url, percentage) = (&b.pr_url.split("pull").next().unwrap()[1..], b.percentage);
+                //     write!(output, "|#{}|{}%|",url.to_owned(), percentage);
+                //     output
+                // })
+
+                let header = match self.data.iter().all(|f| f.pr_url.contains("/pull/")) {
+                    true => "PR",
+                    false => "Issue / PR",
+                };
+
+                format!(
+                    "<table><tr><th>{header}</th><th>Similarity</th></tr>{table_content}</table>"
+                )
+            }
+        }
+    }
+}
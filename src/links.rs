@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    id::{ItemId, ItemKind},
+    SimilarPRs,
+};
+
+/// Issue numbers `body` already references, either as `#123`, `owner/repo#123` or a link to the
+/// issue in `pr`'s repo
+///
+/// ```
+/// use pr_dedupe::{id::ItemKind, links::referenced_issues, ItemId};
+///
+/// let pr = ItemId::new("github.com", "cs50victor/pr_dedupe", ItemKind::Pull, 7).unwrap();
+/// let body = "Fixes #12, see https://github.com/cs50victor/pr_dedupe/issues/3 and other/repo#4";
+/// assert_eq!(referenced_issues(body, &pr).into_iter().collect::<Vec<_>>(), [3, 12]);
+/// ```
+pub fn referenced_issues(body: &str, pr: &ItemId) -> BTreeSet<u64> {
+    let mut issues = BTreeSet::new();
+
+    let repo_name = pr.repo_name();
+    for (i, _) in body.match_indices('#') {
+        let before = &body[..i];
+        let is_reference = match before.chars().next_back() {
+            None => true,
+            Some(c) if c.is_alphanumeric() || matches!(c, '/' | '-' | '_' | '.') => {
+                before.ends_with(&repo_name)
+                    && before[..before.len() - repo_name.len()]
+                        .chars()
+                        .next_back()
+                        .map_or(true, char::is_whitespace)
+            }
+            Some(_) => true,
+        };
+        if let (true, Some(number)) = (is_reference, leading_number(&body[i + 1..])) {
+            issues.insert(number);
+        }
+    }
+
+    let issue_url_prefix = format!(
+        "https://{}/{repo_name}/{}/",
+        pr.host,
+        ItemKind::Issue.as_str()
+    );
+    for (i, _) in body.match_indices(&issue_url_prefix) {
+        if let Some(number) = leading_number(&body[i + issue_url_prefix.len()..]) {
+            issues.insert(number);
+        }
+    }
+
+    issues
+}
+
+/// Drops the similar issues `body` already references, leaving the ones worth suggesting
+pub fn unreferenced_issues(similar_issues: SimilarPRs, body: &str, pr: &ItemId) -> SimilarPRs {
+    let referenced = referenced_issues(body, pr);
+    SimilarPRs {
+        data: similar_issues
+            .data
+            .into_iter()
+            // `#123` only ever refers to the PR's own repo
+            .filter(|issue| {
+                issue.repo.is_some()
+                    || issue
+                        .pr_url
+                        .rsplit('/')
+                        .next()
+                        .and_then(|number| number.parse().ok())
+                        .map_or(true, |number| !referenced.contains(&number))
+            })
+            .collect(),
+    }
+}
+
+/// Renders the suggestions posted in PR comments, one line per issue
+///
+/// ```
+/// use pr_dedupe::{links::may_fix_markdown, SimilarPRs, SimilarPRsInner};
+///
+/// let similar_issues = SimilarPRs {
+///     data: vec![SimilarPRsInner {
+///         pr_url: "https://github.com/cs50victor/pr_dedupe/issues/123".to_string(),
+///         percentage: 87.5,
+///         ..Default::default()
+///     }],
+/// };
+///
+/// assert_eq!(
+///     may_fix_markdown(&similar_issues),
+///     "- this PR may fix #123 (87.5% similar)\n"
+/// );
+/// ```
+pub fn may_fix_markdown(similar_issues: &SimilarPRs) -> String {
+    #[allow(clippy::format_collect)]
+    similar_issues
+        .data
+        .iter()
+        .map(|issue| {
+            format!(
+                "- this PR may fix {}#{} ({}% similar)\n",
+                issue.repo.as_deref().unwrap_or_default(),
+                issue.pr_url.rsplit('/').next().unwrap_or_default(),
+                issue.percentage
+            )
+        })
+        .collect()
+}
+
+fn leading_number(s: &str) -> Option<u64> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimilarPRsInner;
+
+    fn pr() -> ItemId {
+        ItemId::new("github.com", "cs50victor/pr_dedupe", ItemKind::Pull, 7).unwrap()
+    }
+
+    #[test]
+    fn ignores_anchors_and_other_repos() {
+        let body =
+            "see README.md#install, color #fff, other/pr_dedupe#5, x/cs50victor/pr_dedupe#6\n(#8)";
+
+        assert_eq!(
+            referenced_issues(body, &pr())
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [8]
+        );
+    }
+
+    #[test]
+    fn keeps_only_unreferenced_issues() {
+        let issue = |number: u64, percentage| SimilarPRsInner {
+            pr_url: format!("https://github.com/cs50victor/pr_dedupe/issues/{number}"),
+            percentage,
+            ..Default::default()
+        };
+        let similar_issues = SimilarPRs {
+            data: vec![issue(12, 95.0), issue(13, 90.0)],
+        };
+
+        let suggestions = unreferenced_issues(similar_issues, "closes #12", &pr());
+
+        assert_eq!(suggestions.data.len(), 1);
+        assert_eq!(suggestions.data[0].percentage, 90.0);
+    }
+}
@@ -1,84 +1,280 @@
-mod bert;
-mod files_to_ignore;
-mod supabase;
-mod upstash;
-mod utils;
+use std::{env, path::PathBuf, process::ExitCode, sync::Arc};
 
-use std::env;
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use log::{info, warn};
 
-use clap::Parser;
-use futures::stream::StreamExt;
-use log::info;
-
-use serde::{Deserialize, Serialize};
-use upstash::Upstash;
-
-use crate::{
-    files_to_ignore::FILES_TO_IGNORE,
-    utils::{log_err_and_exit, set_hf_home_env, set_output, VectorDB},
+use pr_dedupe::{
+    backfill::Backfill,
+    bert::{Bert, Embedder, EmbeddingVersion, Precision},
+    calibrate::Calibration,
+    content::{build_issue_content, build_pr_content, PrFiles},
+    embedding_cache::EmbeddingCache,
+    error::{self, BackendErrorPolicy, Error},
+    eval::{load_pairs, reports_markdown, EvalReport, ModelReport, PrecisionReport},
+    file_weights::FileWeights,
+    github::GitHub,
+    http::HttpClient,
+    id::{github_host, ItemKind},
+    links::{may_fix_markdown, unreferenced_issues},
+    migrate::MigrateIds,
+    model::{self, Manifest, PinnedModel, Source},
+    reindex::Reindex,
+    rerank::CrossEncoder,
+    scope::{Scope, Visibility},
+    upstash::Upstash,
+    utils::{set_hf_home_env, set_output, EmbeddedItem, QueryOptions, ScoreWeights},
+    ItemId, SimilarPRs, VectorDB,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SimilarPRsInner {
-    pub pr_url: String,
-    pub percentage: f32,
+#[derive(Parser, Debug)]
+#[command(about = "finds duplicate or similar prs in a repo", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Option<Args>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SimilarPRs {
-    pub data: Vec<SimilarPRsInner>,
+impl Cli {
+    /// The model options of commands that embed anything
+    fn model(&self) -> Option<&ModelArgs> {
+        match (&self.command, &self.args) {
+            (
+                Some(
+                    Command::Backfill { model, .. }
+                    | Command::Reindex { model, .. }
+                    | Command::Issue { model, .. }
+                    | Command::Calibrate { model, .. }
+                    | Command::Eval { model, .. }
+                    | Command::CheckPrecision { model, .. },
+                ),
+                _,
+            )
+            | (None, Some(Args { model, .. })) => Some(model),
+            _ => None,
+        }
+    }
 }
 
-impl SimilarPRs {
-    pub fn to_html_table(&self) -> String {
-        match self.data.is_empty() {
-            true => "".into(),
-            false => {
-                #[allow(clippy::format_collect)]
-                let table_content = self
-                    .data
-                    .iter()
-                    .map(|f| {
-                        format!(
-                            "<tr><td>#{}</td><td>{}%</td></tr>",
-                            &f.pr_url.split("pull").nth(1).unwrap()[1..],
-                            f.percentage
-                        )
-                    })
-                    .collect::<String>();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Indexes a repo's existing PRs, so new PRs have something to be compared against
+    Backfill {
+        /// Repo to backfill, as owner/name
+        #[arg(long)]
+        repo: String,
 
-                // self.data.iter().fold(String::from("| PR | Similarity |\n| ---   | --- |\n"), | mut output, b| {
-                //     let (url, percentage) = (&b.pr_url.split("pull").next().unwrap()[1..], b.percentage);
-                //     write!(output, "|#{}|{}%|",url.to_owned(), percentage);
-                //     output
-                // })
+        /// Also index PRs closed on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        closed_since: Option<String>,
 
-                format!("<table><tr><th>PR</th><th>Similarity</th></tr>{table_content}</table>")
-            }
-        }
-    }
+        /// Number of PRs to embed and upsert at a time
+        #[arg(long, default_value_t = 16)]
+        batch_size: usize,
+
+        /// File tracking already indexed PRs, used to resume an interrupted backfill
+        #[arg(long, default_value = "pr_dedupe_backfill.json")]
+        state_file: PathBuf,
+
+        #[arg(long = "db", default_value = "upstash")]
+        vector_db_provider: String,
+
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Re-embeds stored PRs and issues whose vectors were produced by a different model or
+    /// pipeline version
+    Reindex {
+        /// Only reindex this repo's PRs and issues, as owner/name
+        #[arg(long)]
+        repo: Option<String>,
+
+        /// Number of PRs or issues to embed and upsert at a time
+        #[arg(long, default_value_t = 16)]
+        batch_size: usize,
+
+        #[arg(long = "db", default_value = "upstash")]
+        vector_db_provider: String,
+
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Finds open issues and PRs similar to an issue, run on `issues` events
+    Issue {
+        #[arg(long)]
+        closed: String,
+
+        #[arg(long, env = "ISSUE_TITLE")]
+        title: String,
+
+        #[arg(long, env = "ISSUE_BODY", default_value = "")]
+        body: String,
+
+        /// Comma separated label names
+        #[arg(long, env = "ISSUE_LABELS", default_value = "")]
+        labels: String,
+
+        #[command(flatten)]
+        model: ModelArgs,
+
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Moves vectors stored under legacy `owner/repo:number` ids to the current id format
+    MigrateIds {
+        /// Number of ids to migrate at a time
+        #[arg(long, default_value_t = 100)]
+        batch_size: usize,
+
+        #[arg(long = "db", default_value = "upstash")]
+        vector_db_provider: String,
+    },
+    /// Compares how well embedding models tell a labeled set of duplicate PRs apart
+    CompareModels {
+        /// JSONL file of labeled pairs, see `eval::LabeledPair`
+        #[arg(long)]
+        pairs: PathBuf,
+
+        /// Models to compare, as model_id or model_id@revision
+        #[arg(long = "model", required = true)]
+        models: Vec<String>,
+    },
+    /// Recommends a `-m` for a repo from the similarity of its stored, mostly unrelated, PRs.
+    /// In GitHub Actions it's also written to the step's `min_similarity` output
+    Calibrate {
+        /// Repo to calibrate, as owner/name
+        #[arg(long)]
+        repo: String,
+
+        /// Calibrate issue matches instead of PR ones
+        #[arg(long, default_value_t = false)]
+        issues: bool,
+
+        /// Number of stored PRs to sample, every pair of them is scored
+        #[arg(long, default_value_t = 200)]
+        sample: usize,
+
+        /// Highest share of unrelated pairs that may be reported as similar
+        #[arg(long, default_value_t = 0.01)]
+        target_false_positive_rate: f32,
+
+        #[arg(long, default_value_t = ScoreWeights::default().semantic)]
+        semantic_weight: f32,
+
+        #[arg(long, default_value_t = ScoreWeights::default().lexical)]
+        lexical_weight: f32,
+
+        #[arg(long, default_value_t = ScoreWeights::default().paths)]
+        path_weight: f32,
+
+        #[arg(long = "db", default_value = "upstash")]
+        vector_db_provider: String,
+
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Reports precision, recall and F1 per threshold, and the ROC curve, of the whole
+    /// pipeline on a labeled set of PR pairs, to pick `-m` with data
+    Eval {
+        /// JSONL file of labeled pairs, see `eval::LabeledPair`
+        #[arg(long)]
+        pairs: PathBuf,
+
+        /// Percentage between two evaluated thresholds
+        #[arg(long, default_value_t = 5)]
+        step: u8,
+
+        #[arg(long, default_value_t = ScoreWeights::default().semantic)]
+        semantic_weight: f32,
+
+        #[arg(long, default_value_t = ScoreWeights::default().lexical)]
+        lexical_weight: f32,
+
+        #[arg(long, default_value_t = ScoreWeights::default().paths)]
+        path_weight: f32,
+
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Checks or populates the `hub/` model cache
+    Model {
+        #[command(subcommand)]
+        command: ModelCommand,
+    },
+    /// Checks a quantized model's vectors stay within tolerance of the F32 ones on a labeled
+    /// set of PR pairs, failing otherwise
+    CheckPrecision {
+        /// JSONL file of labeled pairs, see `eval::LabeledPair`, labels are ignored
+        #[arg(long)]
+        pairs: PathBuf,
+
+        /// Lowest accepted cosine similarity between both vectors is 1 - tolerance
+        #[arg(long, default_value_t = Precision::TOLERANCE)]
+        tolerance: f32,
+
+        /// Quantized precision checked, the model's own --precision is ignored
+        #[arg(long = "quantized", value_enum, default_value_t = Precision::Int8)]
+        quantized: Precision,
+
+        #[command(flatten)]
+        model: ModelArgs,
+    },
 }
-#[derive(Clone, Copy, Debug)]
-enum FileAction {
-    Added,
-    Modified,
-    Removed,
-    Renamed,
+
+#[derive(Subcommand, Debug)]
+enum ModelCommand {
+    /// Checks the model's files are in the cache with their pinned sha256
+    Verify {
+        #[command(flatten)]
+        files: ModelFilesArgs,
+    },
+    /// Copies the model's files into the cache, checking them against their pinned sha256
+    Fetch {
+        /// Folder holding the model's files, or the url of a Hugging Face mirror
+        #[arg(long)]
+        from: String,
+
+        #[command(flatten)]
+        files: ModelFilesArgs,
+    },
 }
 
-impl From<FileAction> for char {
-    fn from(val: FileAction) -> Self {
-        match val {
-            FileAction::Added => '+',
-            FileAction::Modified => 'M',
-            FileAction::Removed => '-',
-            FileAction::Renamed => '^',
-        }
+/// Which model's files to check, and the sha256 they're pinned to
+#[derive(clap::Args, Debug)]
+struct ModelFilesArgs {
+    #[arg(long, default_value = Bert::MODEL_ID)]
+    model: String,
+
+    #[arg(long, default_value = Bert::REVISION)]
+    model_revision: String,
+
+    /// JSON manifest pinning other models' files, in the format of the repo's models.json.
+    /// Defaults to the models shipped with this release
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+}
+
+impl ModelFilesArgs {
+    fn pinned(&self) -> error::Result<PinnedModel> {
+        let manifest = match &self.manifest {
+            Some(path) => Manifest::load(path).map_err(Error::Config)?,
+            None => Manifest::pinned(),
+        };
+        manifest
+            .get(&self.model, &self.model_revision)
+            .cloned()
+            .ok_or(Error::Config(anyhow!(
+                "{}@{} isn't pinned, list its files' sha256 in a manifest like models.json and pass it with --manifest",
+                self.model,
+                self.model_revision
+            )))
     }
 }
 
-#[derive(Parser, Debug)]
-#[command(about = "finds duplicate or similar prs in a repo", long_about = None)]
+#[derive(clap::Args, Debug)]
 struct Args {
     #[arg(long)]
     closed: String,
@@ -95,6 +291,98 @@ struct Args {
     #[arg(long = "renamed")]
     renamed_files: String,
 
+    /// PR description, issues it already references aren't suggested as fixed by it
+    #[arg(long = "body", env = "PR_BODY", default_value = "")]
+    pr_body: String,
+
+    #[command(flatten)]
+    model: ModelArgs,
+
+    #[command(flatten)]
+    query: QueryArgs,
+}
+
+/// The embedding model. Vectors are only compared with ones from the same model, so every
+/// workflow sharing an index should use the same one
+#[derive(clap::Args, Debug)]
+struct ModelArgs {
+    /// Embedding model, it must be in the `hub/` cache. Code-aware models such as
+    /// flax-sentence-embeddings/st-codesearch-distilroberta-base suit repos that are mostly code,
+    /// models that aren't in models.json must be pinned with --model-manifest
+    #[arg(long, default_value = Bert::MODEL_ID)]
+    model: String,
+
+    #[arg(long, default_value = Bert::REVISION)]
+    model_revision: String,
+
+    /// Prompts embedded per forward pass
+    #[arg(long, default_value_t = Bert::BATCH_SIZE)]
+    inference_batch_size: usize,
+
+    /// Threads used for inference, 0 uses every core
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Folder caching the embeddings of files' chunks, so unchanged files aren't embedded
+    /// again. Keep it between runs, e.g. with actions/cache
+    #[arg(long, env = "PR_DEDUPE_EMBEDDING_CACHE")]
+    embedding_cache: Option<PathBuf>,
+
+    /// How much files count in a PR's vector, as comma separated key=weight pairs. Keys are
+    /// a file kind (source, test, docs, config) or a glob such as vendor/** checked first.
+    /// A weight of 0 leaves matching files out
+    #[arg(long, default_value_t = FileWeights::default())]
+    file_weights: FileWeights,
+
+    /// Weights used for inference. int8 is faster on CPU, run `check-precision` to make sure
+    /// its vectors are close enough to the F32 ones for the model
+    #[arg(long, value_enum, default_value_t = Precision::F32)]
+    precision: Precision,
+
+    /// JSON manifest pinning models that aren't in models.json, e.g. the one passed to
+    /// `model fetch --manifest`. Covers both --model and --rerank-model
+    #[arg(long)]
+    model_manifest: Option<PathBuf>,
+}
+
+impl ModelArgs {
+    /// Fails unless `model_id@revision` is pinned by --model-manifest or models.json, the
+    /// models whose files `model verify` and `model fetch` can check
+    fn check_pinned(&self, model_id: &str, revision: &str) -> error::Result<()> {
+        let manifests = match &self.model_manifest {
+            Some(path) => vec![
+                Manifest::load(path).map_err(Error::Config)?,
+                Manifest::pinned(),
+            ],
+            None => vec![Manifest::pinned()],
+        };
+        match manifests
+            .iter()
+            .any(|manifest| manifest.get(model_id, revision).is_some())
+        {
+            true => Ok(()),
+            false => Err(Error::Config(anyhow!(
+                "{model_id}@{revision} isn't pinned, use a model listed in models.json or list its files' sha256 in a manifest like models.json, fetch them with `model fetch --manifest` and pass the same manifest with --model-manifest"
+            ))),
+        }
+    }
+
+    fn embedder(&self) -> error::Result<Embedder> {
+        self.check_pinned(&self.model, &self.model_revision)?;
+        let embedder = Embedder::new(&self.model, &self.model_revision)
+            .with_batch_size(self.inference_batch_size)
+            .with_precision(self.precision)
+            .with_file_weights(self.file_weights.clone());
+        Ok(match &self.embedding_cache {
+            Some(dir) => embedder.with_cache(EmbeddingCache::new(dir)),
+            None => embedder,
+        })
+    }
+}
+
+/// Options shared by everything that looks up similar PRs or issues
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
     #[arg(long = "db", default_value = "upstash")]
     vector_db_provider: String,
 
@@ -105,172 +393,451 @@ struct Args {
     /// Minimum similarity, in percentage to match for
     #[arg(short = 'm', default_value_t = 80)]
     min_similarity: u8,
+
+    /// Whether a vector database outage fails the check or lets it pass
+    #[arg(long, value_enum, default_value_t = BackendErrorPolicy::Fail)]
+    on_backend_error: BackendErrorPolicy,
+
+    /// Repos matches may come from: 'repo', 'org' or a comma separated list of owner/name repos
+    #[arg(long, default_value_t = Scope::Repo)]
+    scope: Scope,
+
+    /// Comma separated forks or upstreams (owner/name) to always look for the same patch in
+    #[arg(long, default_value = "")]
+    related_repos: String,
+
+    /// Minimum similarity, in percentage, for a match from a related repo to be flagged as the
+    /// same patch
+    #[arg(long, default_value_t = 99)]
+    same_patch_similarity: u8,
+
+    /// Weight of the embeddings' cosine similarity in a match's score
+    #[arg(long, default_value_t = ScoreWeights::default().semantic)]
+    semantic_weight: f32,
+
+    /// Weight of the token overlap (MinHash) similarity in a match's score
+    #[arg(long, default_value_t = ScoreWeights::default().lexical)]
+    lexical_weight: f32,
+
+    /// Weight of the similarity of the touched paths and directories in a PR match's score,
+    /// weighing each by how few of the scope's stored PRs touch it
+    #[arg(long, default_value_t = ScoreWeights::default().paths)]
+    path_weight: f32,
+
+    /// Rescore matches with a cross-encoder, slower but with fewer false positives
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    rerank: bool,
+
+    /// Cross-encoder used to rerank, it must be in the `hub/` cache
+    #[arg(long, default_value = CrossEncoder::MODEL_ID)]
+    rerank_model: String,
+
+    #[arg(long, default_value = CrossEncoder::REVISION)]
+    rerank_revision: String,
+
+    /// Minimum cross-encoder score, in percentage, for a reranked match to be kept. The
+    /// cross-encoder's scores don't follow the same scale as `-m`
+    #[arg(long, default_value_t = 0)]
+    rerank_min_similarity: u8,
 }
 
-#[tokio::main]
-async fn main() {
+/// What's done to the vector db's matches before they're reported
+struct Refine {
+    visibility: Option<Visibility>,
+    reranker: Option<CrossEncoder>,
+    rerank_min_similarity: u8,
+}
+
+impl QueryArgs {
+    fn options(&self, collection: ItemKind) -> QueryOptions {
+        QueryOptions {
+            collection,
+            scope: self.scope.clone(),
+            related_repos: self.related_repos(),
+            top_k: self.top_k,
+            min_similarity: self.min_similarity,
+            same_patch_similarity: self.same_patch_similarity,
+            weights: ScoreWeights {
+                semantic: self.semantic_weight,
+                lexical: self.lexical_weight,
+                paths: self.path_weight,
+            },
+        }
+    }
+
+    fn related_repos(&self) -> Vec<String> {
+        self.related_repos
+            .split(',')
+            .map(str::trim)
+            .filter(|repo| !repo.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Checks repo visibility when matches may come from other repos, and loads the
+    /// cross-encoder when reranking
+    async fn refine(&self, model: &ModelArgs, http: &Arc<HttpClient>) -> error::Result<Refine> {
+        let visibility = match (&self.scope, self.related_repos().is_empty()) {
+            (Scope::Repo, true) => None,
+            _ => Some(Visibility::new(
+                GitHub::new(http.clone()).map_err(Error::Config)?,
+            )),
+        };
+        let reranker = match self.rerank {
+            true => Some({
+                model.check_pinned(&self.rerank_model, &self.rerank_revision)?;
+                CrossEncoder::load(&self.rerank_model, &self.rerank_revision)
+                    .await
+                    .map_err(Error::Model)?
+            }),
+            false => None,
+        };
+        Ok(Refine {
+            visibility,
+            reranker,
+            rerank_min_similarity: self.rerank_min_similarity,
+        })
+    }
+}
+
+/// Queries `options.collection` for matches, without the ones that can't be named in `item`'s
+/// repo, reranked if asked to
+async fn find_similar(
+    vector_db: &impl VectorDB,
+    refine: &mut Refine,
+    item: &EmbeddedItem,
+    options: &QueryOptions,
+    version: &EmbeddingVersion,
+) -> error::Result<SimilarPRs> {
+    let mut similar = vector_db
+        .query(item, options, version)
+        .await
+        .map_err(Error::Backend)?;
+
+    if let Some(visibility) = &mut refine.visibility {
+        similar = visibility
+            .disclosable(&item.id, similar)
+            .await
+            .map_err(Error::Network)?;
+    }
+
+    if let Some(reranker) = &refine.reranker {
+        similar = reranker
+            .rerank(&item.summary, similar)
+            .map_err(Error::Model)?;
+        // matches that weren't reranked have no cross-encoder score to hold to the minimum
+        let min_similarity = refine.rerank_min_similarity as f32;
+        similar
+            .data
+            .retain(|d| !d.rerank.is_some_and(|score| score < min_similarity));
+    }
+
+    Ok(similar)
+}
+
+fn vector_db(vector_db_provider: &str, http: &Arc<HttpClient>) -> error::Result<Upstash> {
+    match vector_db_provider {
+        "upstash" => Upstash::new(http.clone()).map_err(Error::Config),
+        // "supabase" => SB::new().map_err(Error::Config),
+        _ => Err(Error::Config(anyhow!(
+            "Unsupported vector database name. Supported names are 'supabase', 'upstash' "
+        ))),
+    }
+}
+
+fn env_var(key: &str) -> error::Result<String> {
+    env::var(key).map_err(|e| Error::Config(anyhow!("{key} env variable | Reason {e}")))
+}
+
+fn main() -> ExitCode {
     set_hf_home_env();
 
     pretty_env_logger::formatted_builder()
         .filter_module("pr_dedupe", log::LevelFilter::Info)
         .init();
 
-    let args = Args::parse();
+    let cli = Cli::parse();
+
+    // read by candle's cpu backend and rayon alike. Set before the runtime spawns its workers,
+    // the environment can't be safely changed while other threads may read it
+    if let Some(threads) = cli.model().map(|model| model.threads).filter(|&t| t > 0) {
+        env::set_var("RAYON_NUM_THREADS", threads.to_string());
+    }
+
+    let on_backend_error = match (&cli.command, &cli.args) {
+        (Some(Command::Issue { query, .. }), _) | (None, Some(Args { query, .. })) => {
+            query.on_backend_error
+        }
+        _ => BackendErrorPolicy::default(),
+    };
+
+    match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime.block_on(run_command(cli, on_backend_error)),
+        Err(e) => Error::Config(e.into()).report(on_backend_error),
+    }
+}
+
+async fn run_command(cli: Cli, on_backend_error: BackendErrorPolicy) -> ExitCode {
+    let http = match HttpClient::new(reqwest::Client::builder()) {
+        Ok(http) => Arc::new(http),
+        Err(e) => return Error::Config(e).report(on_backend_error),
+    };
+
+    let result = match (cli.command, cli.args) {
+        (
+            Some(Command::Backfill {
+                repo,
+                closed_since,
+                batch_size,
+                state_file,
+                vector_db_provider,
+                model,
+            }),
+            _,
+        ) => match (vector_db(&vector_db_provider, &http), model.embedder()) {
+            (Ok(vector_db), Ok(embedder)) => {
+                Backfill {
+                    repo_name: &repo,
+                    closed_since: closed_since.as_deref(),
+                    batch_size,
+                    state_file: &state_file,
+                    embedder: &embedder,
+                    vector_db: &vector_db,
+                    http: &http,
+                }
+                .run()
+                .await
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        (
+            Some(Command::Reindex {
+                repo,
+                batch_size,
+                vector_db_provider,
+                model,
+            }),
+            _,
+        ) => match (vector_db(&vector_db_provider, &http), model.embedder()) {
+            (Ok(vector_db), Ok(embedder)) => {
+                Reindex {
+                    repo_name: repo.as_deref(),
+                    batch_size,
+                    embedder: &embedder,
+                    vector_db: &vector_db,
+                    http: &http,
+                }
+                .run()
+                .await
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        (
+            Some(Command::Issue {
+                closed,
+                title,
+                body,
+                labels,
+                model,
+                query,
+            }),
+            _,
+        ) => run_issue(&closed, &title, &body, &labels, model, query, &http).await,
+        (
+            Some(Command::MigrateIds {
+                batch_size,
+                vector_db_provider,
+            }),
+            _,
+        ) => match vector_db(&vector_db_provider, &http) {
+            Ok(vector_db) => {
+                MigrateIds {
+                    batch_size,
+                    vector_db: &vector_db,
+                }
+                .run()
+                .await
+            }
+            Err(e) => Err(e),
+        },
+        (Some(Command::CompareModels { pairs, models }), _) => {
+            compare_models(&pairs, &models).await
+        }
+        (
+            Some(Command::Eval {
+                pairs,
+                step,
+                semantic_weight,
+                lexical_weight,
+                path_weight,
+                model,
+            }),
+            _,
+        ) => {
+            let weights = ScoreWeights {
+                semantic: semantic_weight,
+                lexical: lexical_weight,
+                paths: path_weight,
+            };
+            eval(&pairs, step, &weights, model).await
+        }
+        (
+            Some(Command::Calibrate {
+                repo,
+                issues,
+                sample,
+                target_false_positive_rate,
+                semantic_weight,
+                lexical_weight,
+                path_weight,
+                vector_db_provider,
+                model,
+            }),
+            _,
+        ) => match (vector_db(&vector_db_provider, &http), model.embedder()) {
+            (Ok(vector_db), Ok(embedder)) => {
+                let kind = match issues {
+                    true => ItemKind::Issue,
+                    false => ItemKind::Pull,
+                };
+                let weights = ScoreWeights {
+                    semantic: semantic_weight,
+                    lexical: lexical_weight,
+                    paths: path_weight,
+                };
+                calibrate(
+                    &vector_db,
+                    &repo,
+                    kind,
+                    sample,
+                    target_false_positive_rate,
+                    &weights,
+                    &embedder.version(),
+                )
+                .await
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        (Some(Command::Model { command }), _) => run_model(command, &http).await,
+        (
+            Some(Command::CheckPrecision {
+                pairs,
+                tolerance,
+                quantized,
+                model,
+            }),
+            _,
+        ) => check_precision(&pairs, tolerance, quantized, model).await,
+        (None, Some(args)) => run(args, &http).await,
+        (None, None) => unreachable!("clap requires the run args when no subcommand is given"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => e.report(on_backend_error),
+    }
+}
 
+async fn run(args: Args, http: &Arc<HttpClient>) -> error::Result<()> {
     let Args {
         closed,
-        min_similarity,
         added_files,
         modified_files,
         removed_files,
         renamed_files,
-        top_k,
-        vector_db_provider,
+        pr_body,
+        model,
+        query,
     } = args;
 
-    let vector_db = match vector_db_provider.as_str() {
-        "upstash" => match Upstash::new() {
-            Ok(db_client) => db_client,
-            Err(e) => {
-                log_err_and_exit(format!("{e}"));
-            }
-        },
-        // "supabase" => match get_upstash_envs() {
-        //     Ok(envs) => envs,
-        //     Err(e) => {
-        //         log_err_and_exit(format!("{e}"));
-        //     }
-        // },
-        _ => {
-            log_err_and_exit(
-                "Unsupported vector database name. Supported names are 'supabase', 'upstash' ",
-            );
-        }
-    };
+    let vector_db = vector_db(&query.vector_db_provider, http)?;
+    let embedder = model.embedder()?;
+    let embedding_version = embedder.version();
+
+    let pr = ItemId::new(
+        &github_host(),
+        &env_var("REPO_NAME")?,
+        ItemKind::Pull,
+        env_var("PR_NUMBER")?
+            .parse()
+            .map_err(|e| Error::Config(anyhow!("PR_NUMBER must be a number | Reason {e}")))?,
+    )
+    .map_err(|e| Error::Config(e.into()))?;
 
     info!("Created vector db client");
 
-    if closed.trim().parse::<bool>().unwrap() {
-        if let Err(e) = vector_db.remove_pr().await {
-            log_err_and_exit(format!("{e}"));
-        }
+    if parse_closed(&closed)? {
+        vector_db
+            .remove_embedding(&pr)
+            .await
+            .map_err(Error::Backend)?;
         info!("Deleted PR from vector db");
-        return;
+        return Ok(());
     }
 
-    let pr_content = match [
+    let mut pr_files = PrFiles::from_csv(
         &added_files,
         &modified_files,
         &removed_files,
         &renamed_files,
-    ]
-    .iter()
-    .all(|arg| arg.is_empty())
-    {
-        true => {
-            info!("this pr has no content, it's probably a bot or spam");
-            [" ".to_string()].to_vec()
+    );
+    if !pr_files.renamed.is_empty() {
+        // the changed files only list renamed files' new paths
+        let github = GitHub::new(http.clone()).map_err(Error::Config)?;
+        match github.pull_files(&pr.repo_name(), pr.number).await {
+            Ok(files) => pr_files.renamed_from = files.renamed_from,
+            Err(e) => warn!("Couldn't get the old paths of renamed files | Reason {e}"),
         }
-        false => {
-            let raw_url_prefix = format!(
-                "https://github.com/{}/raw/{}/",
-                env::var("REPO_NAME").unwrap(),
-                env::var("GITHUB_SHA").unwrap()
-            );
+    }
+    let base_sha = env::var("BASE_SHA").ok().filter(|sha| !sha.is_empty());
 
-            info!("raw_url_prefix {}", &raw_url_prefix);
+    let pr_content = build_pr_content(
+        http,
+        &pr,
+        &env_var("GITHUB_SHA")?,
+        base_sha.as_deref(),
+        &pr_files,
+    )
+    .await
+    .map_err(Error::Network)?;
 
-            let pr_files = added_files
-                .split(',')
-                .map(|file| (file, FileAction::Added))
-                .chain(
-                    modified_files
-                        .split(',')
-                        .map(|file| (file, FileAction::Modified)),
-                )
-                .filter(|(file, _)| {
-                    !file.is_empty()
-                        && !FILES_TO_IGNORE.iter().any(|&suffix| file.ends_with(suffix))
-                })
-                .map(|(file, action)| (format!("{}{file}", &raw_url_prefix), action));
-
-            info!(
-                "downloading PR files | {:?}",
-                pr_files.clone().collect::<Vec<_>>()
-            );
+    let embedding = embedder.embed(&pr_content).await.map_err(Error::Model)?;
+    let item = EmbeddedItem::new(pr.clone(), &pr_content, embedding);
 
-            let mut pr_content =
-                futures::stream::iter(pr_files.map(|(path, file_type)| async move {
-                    match reqwest::get(&path).await {
-                        Ok(resp) => match resp.bytes().await {
-                            Ok(resp_bytes) => {
-                                let content = std::str::from_utf8(&resp_bytes).unwrap();
-
-                                match file_type {
-                                    FileAction::Added | FileAction::Modified => {
-                                        parse(file_type, &path, Some(content))
-                                    }
-                                    _ => {
-                                        let symbol: char = file_type.into();
-                                        log_err_and_exit(format!(
-                                            "Unexpected Filetype. Symbol {}",
-                                            symbol
-                                        ));
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                log_err_and_exit(format!("{e}"));
-                            }
-                        },
-                        Err(e) => {
-                            log_err_and_exit(format!("Couldn't download {path} | Reason {e:?}"));
-                        }
-                    }
-                }))
-                .buffer_unordered(10)
-                .collect::<Vec<String>>()
-                .await;
-
-            pr_content.extend(
-                removed_files
-                    .split(',')
-                    .map(|file| (file, FileAction::Removed))
-                    .chain(
-                        renamed_files
-                            .split(',')
-                            .map(|file| (file, FileAction::Renamed)),
-                    )
-                    .filter(|(file, _)| !file.is_empty())
-                    .map(|(file, file_action)| {
-                        parse(file_action, &format!("{}{file}", &raw_url_prefix), None)
-                    }),
-            );
-            pr_content
-        }
-    };
+    let mut refine = query.refine(&model, http).await?;
 
-    let embedding = match bert::generate_embeddings(pr_content, 384).await {
-        Ok(embedding) => embedding,
-        Err(e) => {
-            log_err_and_exit(format!("{e}"));
-        }
-    };
-
-    let similar_prs = match vector_db.query(&embedding, top_k, min_similarity).await {
-        Ok(resp) => resp,
-        Err(e) => {
-            log_err_and_exit(format!("{e}"));
-        }
-    };
+    let similar_prs = find_similar(
+        &vector_db,
+        &mut refine,
+        &item,
+        &query.options(ItemKind::Pull),
+        &embedding_version,
+    )
+    .await?;
 
     let similar_prs_str = serde_json::to_string(&similar_prs).unwrap();
 
     info!("Queried for similar PRs");
 
-    if let Err(e) = vector_db.save_embedding(&embedding).await {
-        log_err_and_exit(format!("{e}"));
-    }
+    let similar_issues = find_similar(
+        &vector_db,
+        &mut refine,
+        &item,
+        &query.options(ItemKind::Issue),
+        &embedding_version,
+    )
+    .await?;
+    let similar_issues = unreferenced_issues(similar_issues, &pr_body, &pr);
+
+    info!("Queried for issues this PR may fix");
+
+    vector_db
+        .save_embedding(&item, &embedding_version)
+        .await
+        .map_err(Error::Backend)?;
 
     info!("Saved embedding");
 
@@ -278,20 +845,250 @@ async fn main() {
     let x = &similar_prs.to_html_table();
     info!("Similar PRs markdown : {x}");
 
-    set_output("similar_prs", &similar_prs_str);
+    write_outputs(&similar_prs)?;
+
+    let github_output = env_var("GITHUB_OUTPUT")?;
     set_output(
-        "similar_prs_markdown",
-        &serde_json::to_string(&similar_prs.to_html_table()).unwrap(),
-    );
+        &github_output,
+        "similar_issues",
+        &serde_json::to_string(&similar_issues).unwrap(),
+    )
+    .and_then(|_| {
+        set_output(
+            &github_output,
+            "similar_issues_markdown",
+            &serde_json::to_string(&may_fix_markdown(&similar_issues)).unwrap(),
+        )
+    })
+    .map_err(|e| Error::Config(anyhow!("Couldn't write to GITHUB_OUTPUT | Reason {e}")))
 }
 
-fn parse(file_type: FileAction, path: &str, content: Option<&str>) -> String {
-    let symbol: char = file_type.into();
-    match content {
-        Some(c) => {
-            info!("parsed {path}'s content");
-            format!("{symbol} : {path}\n{c}\n")
+/// Embeds the issue, reports the open issues and PRs closest to it and stores it for later
+/// issues to be compared against
+async fn run_issue(
+    closed: &str,
+    title: &str,
+    body: &str,
+    labels: &str,
+    model: ModelArgs,
+    query: QueryArgs,
+    http: &Arc<HttpClient>,
+) -> error::Result<()> {
+    let vector_db = vector_db(&query.vector_db_provider, http)?;
+    let embedder = model.embedder()?;
+    let embedding_version = embedder.version();
+
+    let issue = ItemId::new(
+        &github_host(),
+        &env_var("REPO_NAME")?,
+        ItemKind::Issue,
+        env_var("ISSUE_NUMBER")?
+            .parse()
+            .map_err(|e| Error::Config(anyhow!("ISSUE_NUMBER must be a number | Reason {e}")))?,
+    )
+    .map_err(|e| Error::Config(e.into()))?;
+
+    if parse_closed(closed)? {
+        vector_db
+            .remove_embedding(&issue)
+            .await
+            .map_err(Error::Backend)?;
+        info!("Deleted issue from vector db");
+        return Ok(());
+    }
+
+    let labels = labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let issue_content = build_issue_content(title, body, &labels);
+    let embedding = embedder.embed(&issue_content).await.map_err(Error::Model)?;
+    let item = EmbeddedItem::new(issue, &issue_content, embedding);
+
+    let mut refine = query.refine(&model, http).await?;
+    let mut similar = SimilarPRs { data: vec![] };
+    for collection in [ItemKind::Issue, ItemKind::Pull] {
+        let matches = find_similar(
+            &vector_db,
+            &mut refine,
+            &item,
+            &query.options(collection),
+            &embedding_version,
+        )
+        .await?;
+        similar = similar.merge(matches, query.top_k);
+    }
+
+    info!("Queried for similar issues and PRs");
+
+    vector_db
+        .save_embedding(&item, &embedding_version)
+        .await
+        .map_err(Error::Backend)?;
+
+    info!("Saved embedding");
+
+    write_outputs(&similar)
+}
+
+/// Scores the labeled pairs with every model and prints a markdown table comparing them
+async fn compare_models(pairs: &std::path::Path, models: &[String]) -> error::Result<()> {
+    let pairs = load_pairs(pairs).map_err(Error::Config)?;
+    info!("loaded {} labeled pairs", pairs.len());
+
+    let mut reports = Vec::with_capacity(models.len());
+    for model in models {
+        let (model_id, revision) = model.split_once('@').unwrap_or((model, "main"));
+        reports.push(
+            ModelReport::new(&Embedder::new(model_id, revision), &pairs)
+                .await
+                .map_err(Error::Model)?,
+        );
+    }
+
+    println!("{}", reports_markdown(&reports));
+    Ok(())
+}
+
+/// Samples the repo's stored vectors and prints the threshold keeping unrelated matches under
+/// the target rate
+async fn calibrate(
+    vector_db: &impl VectorDB,
+    repo_name: &str,
+    kind: ItemKind,
+    sample: usize,
+    target_false_positive_rate: f32,
+    weights: &ScoreWeights,
+    version: &EmbeddingVersion,
+) -> error::Result<()> {
+    let items = vector_db
+        .sample_embeddings(repo_name, kind, version, sample)
+        .await
+        .map_err(Error::Backend)?;
+    info!("sampled {} stored embeddings from {repo_name}", items.len());
+
+    let calibration = Calibration::new(&items, weights, target_false_positive_rate).ok_or(
+        Error::Config(anyhow!(
+            "{repo_name} needs at least 2 stored {kind:?} embeddings from {} to calibrate, backfill it first",
+            version.model_id
+        )),
+    )?;
+
+    println!("{}", calibration.markdown());
+
+    if let Ok(github_output) = env::var("GITHUB_OUTPUT") {
+        set_output(
+            &github_output,
+            "min_similarity",
+            &calibration.threshold.to_string(),
+        )
+        .map_err(|e| Error::Config(anyhow!("Couldn't write to GITHUB_OUTPUT | Reason {e}")))?;
+    }
+    Ok(())
+}
+
+/// Scores the labeled pairs like a query would and prints the metrics per threshold
+async fn eval(
+    pairs: &std::path::Path,
+    step: u8,
+    weights: &ScoreWeights,
+    model: ModelArgs,
+) -> error::Result<()> {
+    let pairs = load_pairs(pairs).map_err(Error::Config)?;
+    let report = EvalReport::new(&model.embedder()?, &pairs, weights, step)
+        .await
+        .map_err(Error::Model)?;
+
+    println!("{}", report.markdown());
+    Ok(())
+}
+
+async fn run_model(command: ModelCommand, http: &HttpClient) -> error::Result<()> {
+    let cache = hf_hub::Cache::default().path().clone();
+    match command {
+        ModelCommand::Verify { files } => {
+            let pinned = files.pinned()?;
+            let problems = model::verify(&cache, &pinned).map_err(Error::Model)?;
+            if !problems.is_empty() {
+                let problems = problems
+                    .iter()
+                    .map(|p| format!("\n - {p}"))
+                    .collect::<String>();
+                return Err(Error::Model(anyhow!(
+                    "{}@{} in {} failed verification:{problems}",
+                    pinned.model_id,
+                    pinned.revision,
+                    cache.display()
+                )));
+            }
+            println!("{}@{} verified", pinned.model_id, pinned.revision);
+            Ok(())
+        }
+        ModelCommand::Fetch { from, files } => {
+            let pinned = files.pinned()?;
+            model::fetch(&cache, &pinned, &Source::from(from.as_str()), http).await?;
+            println!(
+                "fetched {}@{} into {}",
+                pinned.model_id,
+                pinned.revision,
+                cache.display()
+            );
+            Ok(())
         }
-        None => format!("{symbol} : {path}\n"),
     }
 }
+
+/// Embeds the pairs at F32 and `quantized` precision and fails if any vector drifts further
+/// than `tolerance`
+async fn check_precision(
+    pairs: &std::path::Path,
+    tolerance: f32,
+    quantized: Precision,
+    model: ModelArgs,
+) -> error::Result<()> {
+    let pairs = load_pairs(pairs).map_err(Error::Config)?;
+    let report = PrecisionReport::new(
+        &model.embedder()?.with_precision(Precision::F32),
+        &model.embedder()?.with_precision(quantized),
+        &pairs,
+    )
+    .await
+    .map_err(Error::Model)?;
+
+    println!("{}", report.markdown());
+    match report.within(tolerance) {
+        true => Ok(()),
+        false => Err(Error::Model(anyhow!(
+            "{quantized:?} vectors of {} drift up to {:.4} from F32 ones, more than the {tolerance} tolerance, keep --precision f32",
+            model.model,
+            1.0 - report.min_cosine
+        ))),
+    }
+}
+
+fn parse_closed(closed: &str) -> error::Result<bool> {
+    closed
+        .trim()
+        .parse::<bool>()
+        .map_err(|e| Error::Config(anyhow!("--closed expects true or false | Reason {e}")))
+}
+
+fn write_outputs(similar_prs: &SimilarPRs) -> error::Result<()> {
+    let github_output = env_var("GITHUB_OUTPUT")?;
+    set_output(
+        &github_output,
+        "similar_prs",
+        &serde_json::to_string(similar_prs).unwrap(),
+    )
+    .and_then(|_| {
+        set_output(
+            &github_output,
+            "similar_prs_markdown",
+            &serde_json::to_string(&similar_prs.to_html_table()).unwrap(),
+        )
+    })
+    .map_err(|e| Error::Config(anyhow!("Couldn't write to GITHUB_OUTPUT | Reason {e}")))
+}
@@ -0,0 +1,55 @@
+use log::{info, warn};
+
+use crate::{
+    error::{self, Error},
+    id::{is_legacy, ItemId},
+    utils::VectorDB,
+};
+
+/// Moves vectors stored under legacy `owner/repo:number` ids to the current id format
+pub struct MigrateIds<'a, DB: VectorDB> {
+    pub batch_size: usize,
+    pub vector_db: &'a DB,
+}
+
+impl<DB: VectorDB> MigrateIds<'_, DB> {
+    pub async fn run(&self) -> error::Result<()> {
+        let stored = self
+            .vector_db
+            .list_embeddings()
+            .await
+            .map_err(Error::Backend)?;
+
+        let renames = stored
+            .iter()
+            .filter(|e| is_legacy(&e.id))
+            .filter_map(|e| match ItemId::from_legacy(&e.id) {
+                Ok(id) => Some((e.id.clone(), id)),
+                Err(err) => {
+                    warn!("can't migrate {} | Reason {err}", e.id);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        info!(
+            "migrating {}/{} stored embeddings to the new id format",
+            renames.len(),
+            stored.len()
+        );
+
+        let mut done = 0;
+        for batch in renames.chunks(self.batch_size.max(1)) {
+            self.vector_db
+                .rename_embeddings(batch)
+                .await
+                .map_err(Error::Backend)?;
+
+            done += batch.len();
+            info!("migrated {done}/{} ids", renames.len());
+        }
+
+        info!("finished migrating ids");
+        Ok(())
+    }
+}
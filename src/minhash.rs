@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fingerprint;
+
+/// hash functions in a signature
+pub const NUM_HASHES: usize = 128;
+/// LSH bands, each covering `NUM_HASHES / LSH_BANDS` hashes. With 32 bands of 4 rows, pairs
+/// around 40% similar or more very likely share a band
+pub const LSH_BANDS: usize = 32;
+/// tokens per shingle
+const SHINGLE_LEN: usize = 5;
+
+/// 2^61 - 1, the prime the hash permutations are computed modulo
+const MERSENNE_61: u64 = (1 << 61) - 1;
+
+/// MinHash signature of a PR's token shingles, estimating the Jaccard similarity of two PRs'
+/// code independently of the embedding model.
+///
+/// Shingles are built from the raw tokens and from the tokens with identifiers replaced by a
+/// placeholder, so renamed identifiers still share the latter, and as they're a set,
+/// reordering functions doesn't change much
+///
+/// ```
+/// use pr_dedupe::minhash::LexicalSignature;
+///
+/// let original = ["+ : src/a.rs\nfn total(items: &[u32]) -> u32 { items.iter().sum() }\n".to_string()];
+/// let renamed = ["+ : src/a.rs\nfn sum_all(values: &[u32]) -> u32 { values.iter().sum() }\n".to_string()];
+///
+/// let original = LexicalSignature::new(&original).unwrap();
+/// assert_eq!(original.similarity(&original), 1.0);
+/// assert!(original.similarity(&LexicalSignature::new(&renamed).unwrap()) > 0.3);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LexicalSignature {
+    pub minhash: Vec<u32>,
+    /// one key per band, signatures sharing a key are LSH candidates
+    pub lsh_bands: Vec<String>,
+}
+
+impl LexicalSignature {
+    /// Signs the content built by [`crate::build_pr_content`] or [`crate::build_issue_content`],
+    /// `None` when it has no tokens
+    pub fn new(content: &[String]) -> Option<Self> {
+        let shingles = shingles(content);
+        if shingles.is_empty() {
+            return None;
+        }
+
+        let minhash = (0..NUM_HASHES as u64)
+            .map(|i| {
+                let (a, b) = permutation(i);
+                shingles
+                    .iter()
+                    .map(|&shingle| {
+                        ((a as u128 * (shingle % MERSENNE_61) as u128 + b as u128)
+                            % MERSENNE_61 as u128) as u32
+                    })
+                    .min()
+                    .unwrap_or(u32::MAX)
+            })
+            .collect::<Vec<_>>();
+
+        let rows = NUM_HASHES / LSH_BANDS;
+        let lsh_bands = minhash
+            .chunks(rows)
+            .enumerate()
+            .map(|(band, rows)| {
+                let bytes = rows
+                    .iter()
+                    .flat_map(|r| r.to_le_bytes())
+                    .collect::<Vec<_>>();
+                format!("{band}:{:016x}", fnv1a(&bytes))
+            })
+            .collect();
+
+        Some(Self { minhash, lsh_bands })
+    }
+
+    /// Estimated Jaccard similarity of both PRs' shingles, from 0 to 1
+    pub fn similarity(&self, other: &LexicalSignature) -> f32 {
+        if self.minhash.len() != other.minhash.len() || self.minhash.is_empty() {
+            return 0.0;
+        }
+        let equal = self
+            .minhash
+            .iter()
+            .zip(&other.minhash)
+            .filter(|(a, b)| a == b)
+            .count();
+        equal as f32 / self.minhash.len() as f32
+    }
+}
+
+/// Hashed shingles of every entry's normalized content
+fn shingles(content: &[String]) -> HashSet<u64> {
+    let mut shingles = HashSet::new();
+
+    for entry in content {
+        let (_, body) = fingerprint::normalize(entry);
+        let tokens = tokenize(&body);
+        if tokens.is_empty() {
+            continue;
+        }
+        let placeholders = tokens
+            .iter()
+            .map(|token| match is_identifier(token) && !is_keyword(token) {
+                true => "$id",
+                false => token,
+            })
+            .collect::<Vec<_>>();
+
+        for (tag, tokens) in [(b'r', tokens.as_slice()), (b'p', placeholders.as_slice())] {
+            for window in tokens.windows(SHINGLE_LEN.min(tokens.len())) {
+                let mut bytes = vec![tag];
+                for token in window {
+                    bytes.extend_from_slice(token.as_bytes());
+                    bytes.push(0);
+                }
+                shingles.insert(fnv1a(&bytes));
+            }
+        }
+    }
+
+    shingles
+}
+
+/// Words, numbers and single punctuation characters
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices() {
+        let is_word = c.is_alphanumeric() || c == '_';
+        match (start, is_word) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                tokens.push(&text[s..i]);
+                start = None;
+            }
+            _ => {}
+        }
+        if !is_word && !c.is_whitespace() {
+            tokens.push(&text[i..i + c.len_utf8()]);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&text[s..]);
+    }
+
+    tokens
+}
+
+fn is_identifier(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+}
+
+/// keywords shared by the languages PRs are most often written in, kept as is so the
+/// placeholder shingles still capture the code's structure
+fn is_keyword(token: &str) -> bool {
+    matches!(
+        token,
+        "fn" | "def"
+            | "function"
+            | "func"
+            | "class"
+            | "struct"
+            | "enum"
+            | "impl"
+            | "trait"
+            | "interface"
+            | "let"
+            | "const"
+            | "var"
+            | "mut"
+            | "pub"
+            | "return"
+            | "if"
+            | "else"
+            | "for"
+            | "while"
+            | "loop"
+            | "match"
+            | "switch"
+            | "case"
+            | "break"
+            | "continue"
+            | "import"
+            | "from"
+            | "use"
+            | "async"
+            | "await"
+            | "try"
+            | "catch"
+            | "except"
+            | "new"
+            | "self"
+            | "this"
+            | "true"
+            | "false"
+            | "null"
+            | "None"
+    )
+}
+
+/// `a` and `b` of the `i`th `(a * x + b) mod p` permutation, derived with splitmix64 so
+/// signatures stay comparable across builds
+fn permutation(i: u64) -> (u64, u64) {
+    let a = splitmix64(2 * i) % (MERSENNE_61 - 1) + 1;
+    let b = splitmix64(2 * i + 1) % MERSENNE_61;
+    (a, b)
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(code: &str) -> LexicalSignature {
+        LexicalSignature::new(&[format!("+ : src/lib.rs\n{code}\n")]).unwrap()
+    }
+
+    const FUNCTIONS: &str = "fn parse(input: &str) -> Vec<u32> {\n    input.split(',').map(|n| n.trim().parse().unwrap()).collect()\n}\n\nfn total(values: &[u32]) -> u32 {\n    values.iter().copied().sum()\n}";
+    const REORDERED: &str = "fn total(values: &[u32]) -> u32 {\n    values.iter().copied().sum()\n}\n\nfn parse(input: &str) -> Vec<u32> {\n    input.split(',').map(|n| n.trim().parse().unwrap()).collect()\n}";
+    const UNRELATED: &str =
+        "SELECT name, email FROM users WHERE created_at > now() - interval '1 day' ORDER BY name;";
+
+    #[test]
+    fn reordered_functions_stay_similar() {
+        assert!(signature(FUNCTIONS).similarity(&signature(REORDERED)) > 0.7);
+        assert!(signature(FUNCTIONS).similarity(&signature(UNRELATED)) < 0.1);
+    }
+
+    #[test]
+    fn similar_prs_share_an_lsh_band() {
+        let a = signature(FUNCTIONS);
+        let b = signature(REORDERED);
+
+        assert_eq!(a.lsh_bands.len(), LSH_BANDS);
+        assert!(a.lsh_bands.iter().any(|band| b.lsh_bands.contains(band)));
+    }
+
+    #[test]
+    fn empty_content_has_no_signature() {
+        assert_eq!(LexicalSignature::new(&[" ".to_string()]), None);
+    }
+}
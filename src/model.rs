@@ -0,0 +1,330 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use hf_hub::{Repo, RepoType};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{self, Error},
+    fingerprint::hex,
+    http::HttpClient,
+};
+
+/// sha256 of the models' files shipped in `hub/`, built into the binary
+const PINNED: &str = include_str!("../models.json");
+
+/// the weights take longer than a request's default timeout to download
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A model revision's files and their sha256
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PinnedModel {
+    pub model_id: String,
+    pub revision: String,
+    /// snapshot the revision points to in the cache
+    pub commit: String,
+    pub files: BTreeMap<String, String>,
+}
+
+impl PinnedModel {
+    fn repo_dir(&self, cache: &Path) -> PathBuf {
+        let repo = Repo::with_revision(
+            self.model_id.clone(),
+            RepoType::Model,
+            self.revision.clone(),
+        );
+        cache.join(repo.folder_name())
+    }
+
+    fn ref_path(&self, cache: &Path) -> PathBuf {
+        self.repo_dir(cache).join("refs").join(&self.revision)
+    }
+
+    fn snapshot_dir(&self, cache: &Path) -> PathBuf {
+        self.repo_dir(cache).join("snapshots").join(&self.commit)
+    }
+}
+
+/// Models whose files can be verified and fetched
+///
+/// ```
+/// use pr_dedupe::{model::Manifest, Bert};
+///
+/// assert!(Manifest::pinned().get(Bert::MODEL_ID, Bert::REVISION).is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Manifest(Vec<PinnedModel>);
+
+impl Manifest {
+    /// The manifest of the models shipped with this release
+    pub fn pinned() -> Self {
+        Self(serde_json::from_str(PINNED).expect("models.json is a valid manifest"))
+    }
+
+    /// A manifest in the same format as `models.json`, e.g. to pin other models
+    pub fn load(path: &Path) -> Result<Self> {
+        let manifest = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Couldn't read {} | Reason {e}", path.display()))?;
+        serde_json::from_str(&manifest)
+            .map(Self)
+            .map_err(|e| anyhow!("{} isn't a model manifest | Reason {e}", path.display()))
+    }
+
+    pub fn get(&self, model_id: &str, revision: &str) -> Option<&PinnedModel> {
+        self.0
+            .iter()
+            .find(|m| m.model_id == model_id && m.revision == revision)
+    }
+}
+
+/// Something wrong with a model in the cache, with how to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// `refs/<revision>` is missing or points to another snapshot
+    Ref {
+        expected: String,
+        actual: Option<String>,
+    },
+    Missing {
+        file: String,
+    },
+    Mismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Ref { expected, actual: None } => write!(
+                f,
+                "the revision isn't in the cache, it should point to snapshot {expected}. Run `model fetch`"
+            ),
+            Problem::Ref { expected, actual: Some(actual) } => write!(
+                f,
+                "the revision points to snapshot {actual} instead of {expected}. Run `model fetch` to replace it"
+            ),
+            Problem::Missing { file } => {
+                write!(f, "{file} is missing. Run `model fetch` to download it")
+            }
+            Problem::Mismatch { file, expected, actual } => write!(
+                f,
+                "{file} has sha256 {actual} instead of {expected}, it's corrupt or from another revision. Run `model fetch` to replace it"
+            ),
+        }
+    }
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    Ok(hex(&Sha256::digest(fs::read(path)?)))
+}
+
+/// Checks the model's files are in the `cache` folder, e.g. `$HF_HOME/hub`, with their pinned
+/// sha256. Empty when everything matches
+pub fn verify(cache: &Path, model: &PinnedModel) -> Result<Vec<Problem>> {
+    let mut problems = vec![];
+    let actual = fs::read_to_string(model.ref_path(cache))
+        .ok()
+        .map(|commit| commit.trim().to_string());
+    if actual.as_ref() != Some(&model.commit) {
+        problems.push(Problem::Ref {
+            expected: model.commit.clone(),
+            actual,
+        });
+    }
+
+    let snapshot = model.snapshot_dir(cache);
+    for (file, expected) in &model.files {
+        let path = snapshot.join(file);
+        if !path.exists() {
+            problems.push(Problem::Missing { file: file.clone() });
+            continue;
+        }
+        let actual = sha256(&path)?;
+        if &actual != expected {
+            problems.push(Problem::Mismatch {
+                file: file.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Where `model fetch` copies a model's files from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// folder holding the files, e.g. a clone of the model's repo
+    Dir(PathBuf),
+    /// Hugging Face compatible mirror, files are downloaded from
+    /// `<url>/<model_id>/resolve/<commit>/<file>`
+    Mirror(String),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Dir(dir) => write!(f, "{}", dir.display()),
+            Source::Mirror(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+impl From<&str> for Source {
+    fn from(from: &str) -> Self {
+        match from.starts_with("http://") || from.starts_with("https://") {
+            true => Source::Mirror(from.trim_end_matches('/').to_string()),
+            false => Source::Dir(from.into()),
+        }
+    }
+}
+
+impl Source {
+    /// A folder that can't be read is a configuration error, a mirror that can't be reached a
+    /// network one
+    async fn read(
+        &self,
+        http: &HttpClient,
+        model: &PinnedModel,
+        file: &str,
+    ) -> error::Result<Vec<u8>> {
+        match self {
+            Source::Dir(dir) => {
+                let path = dir.join(file);
+                fs::read(&path).map_err(|e| {
+                    Error::Config(anyhow!("Couldn't read {} | Reason {e}", path.display()))
+                })
+            }
+            Source::Mirror(url) => {
+                let url = format!("{url}/{}/resolve/{}/{file}", model.model_id, model.commit);
+                let download = async {
+                    let resp = http.send(http.get(&url).timeout(DOWNLOAD_TIMEOUT)).await?;
+                    if !resp.status().is_success() {
+                        return Err(anyhow!("GET {url} returned {}", resp.status()));
+                    }
+                    Ok(resp.bytes().await?)
+                };
+                download.await.map_err(Error::Network)
+            }
+        }
+    }
+}
+
+/// Errors reading or writing the cache folder
+fn cache_error<E: Display>(path: &Path) -> impl FnOnce(E) -> Error + '_ {
+    move |e| Error::Model(anyhow!("Couldn't update {} | Reason {e}", path.display()))
+}
+
+/// Copies the model's files from `source` into the `cache` folder, checking every file
+/// against its pinned sha256 before it's written. Files already in place are kept.
+///
+/// Fails with [`Error::Network`] when a mirror can't be reached, [`Error::Config`] when the
+/// source folder can't be read and [`Error::Model`] when a file doesn't match its pin or the
+/// cache can't be written
+pub async fn fetch(
+    cache: &Path,
+    model: &PinnedModel,
+    source: &Source,
+    http: &HttpClient,
+) -> error::Result<()> {
+    let snapshot = model.snapshot_dir(cache);
+    fs::create_dir_all(&snapshot).map_err(cache_error(&snapshot))?;
+
+    for (file, expected) in &model.files {
+        let path = snapshot.join(file);
+        if path.exists() && &sha256(&path).map_err(cache_error(&path))? == expected {
+            info!("{file} already in the cache");
+            continue;
+        }
+
+        let bytes = source.read(http, model, file).await?;
+        let actual = hex(&Sha256::digest(&bytes));
+        if &actual != expected {
+            return Err(Error::Model(anyhow!(
+                "{file} from {source} has sha256 {actual} instead of {expected}, it's from another revision of {}",
+                model.model_id
+            )));
+        }
+        // written next to its destination then renamed, so an interrupted fetch leaves no
+        // truncated file behind. Replaces the symlink to `blobs/` hf-hub would have created
+        let partial = snapshot.join(format!("{file}.partial"));
+        fs::write(&partial, bytes).map_err(cache_error(&partial))?;
+        if path.is_symlink() {
+            fs::remove_file(&path).map_err(cache_error(&path))?;
+        }
+        fs::rename(&partial, &path).map_err(cache_error(&path))?;
+        info!("fetched {file}");
+    }
+
+    let ref_path = model.ref_path(cache);
+    let refs = ref_path.parent().unwrap_or(cache);
+    fs::create_dir_all(refs).map_err(cache_error(refs))?;
+    fs::write(&ref_path, &model.commit).map_err(cache_error(&ref_path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fetches_then_verifies_a_local_model() {
+        let dir = std::env::temp_dir().join(format!("pr_dedupe_model_{}", std::process::id()));
+        let (source, cache) = (dir.join("source"), dir.join("hub"));
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("config.json"), "{}").unwrap();
+        let model = PinnedModel {
+            model_id: "owner/model".to_string(),
+            revision: "main".to_string(),
+            commit: "abc".to_string(),
+            files: BTreeMap::from([("config.json".to_string(), hex(&Sha256::digest("{}")))]),
+        };
+
+        let missing = verify(&cache, &model).unwrap();
+        let http = HttpClient::new(reqwest::Client::builder()).unwrap();
+        fetch(&cache, &model, &Source::Dir(source.clone()), &http)
+            .await
+            .unwrap();
+        let fetched = verify(&cache, &model).unwrap();
+        fs::write(model.snapshot_dir(&cache).join("config.json"), "[]").unwrap();
+        let corrupt = verify(&cache, &model).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(missing.len(), 2);
+        assert_eq!(fetched, vec![]);
+        assert!(matches!(&corrupt[..], [Problem::Mismatch { file, .. }] if file == "config.json"));
+    }
+
+    #[tokio::test]
+    async fn fetch_errors_tell_a_bad_source_from_a_corrupt_file() {
+        let dir = std::env::temp_dir().join(format!("pr_dedupe_fetch_{}", std::process::id()));
+        let (source, cache) = (dir.join("source"), dir.join("hub"));
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("config.json"), "[]").unwrap();
+        let model = PinnedModel {
+            model_id: "owner/model".to_string(),
+            revision: "main".to_string(),
+            commit: "abc".to_string(),
+            files: BTreeMap::from([("config.json".to_string(), hex(&Sha256::digest("{}")))]),
+        };
+        let http = HttpClient::new(reqwest::Client::builder()).unwrap();
+
+        let corrupt = fetch(&cache, &model, &Source::Dir(source), &http).await;
+        let missing = fetch(&cache, &model, &Source::Dir(dir.join("nowhere")), &http).await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(corrupt, Err(Error::Model(_))));
+        assert!(matches!(missing, Err(Error::Config(_))));
+    }
+}
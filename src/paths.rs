@@ -0,0 +1,150 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fingerprint::normalize;
+
+/// max paths stored per PR, PRs touching more are compared on their first ones
+const MAX_PATHS: usize = 300;
+
+/// Paths a PR touches, compared with a weighted Jaccard similarity over the paths and their
+/// directories, so PRs changing the same files or neighbouring ones match even when their
+/// code differs.
+///
+/// Every path and directory weighs how rare it is among the PRs counted by
+/// [`PathFrequencies`], so sharing a directory few PRs touch counts more than sharing one
+/// like `src/` that most do
+///
+/// ```
+/// use pr_dedupe::paths::{PathFrequencies, PathSet};
+///
+/// let content = |path: &str| [format!("M : https://github.com/acme/app/raw/abc/{path}\nfn a() {{}}\n")];
+/// let parser = PathSet::new(&content("src/parser/lexer.rs")).unwrap();
+/// let sibling = PathSet::new(&content("src/parser/ast.rs")).unwrap();
+/// let elsewhere = PathSet::new(&content("src/cli.rs")).unwrap();
+/// let frequencies = PathFrequencies::new([&parser, &sibling, &elsewhere]);
+///
+/// assert_eq!(parser.similarity(&parser, &frequencies), 1.0);
+/// assert!(parser.similarity(&sibling, &frequencies) > parser.similarity(&elsewhere, &frequencies));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PathSet {
+    pub paths: Vec<String>,
+}
+
+impl PathSet {
+    /// The paths of the files in content built by [`crate::build_pr_content`], that is the
+    /// `--added`, `--modified`, `--removed` and `--renamed` files. `None` without any file
+    pub fn new(content: &[String]) -> Option<Self> {
+        let paths = content
+            .iter()
+            .filter_map(|entry| normalize(entry).0)
+            .collect::<BTreeSet<_>>();
+        match paths.is_empty() {
+            true => None,
+            false => Some(Self {
+                paths: paths.into_iter().take(MAX_PATHS).collect(),
+            }),
+        }
+    }
+
+    /// Every touched path and its parent directories, directories ending with `/`
+    fn elements(&self) -> BTreeSet<&str> {
+        self.paths
+            .iter()
+            .flat_map(|path| {
+                let dirs = path.match_indices('/').map(|(i, _)| &path[..=i]);
+                dirs.chain([path.as_str()])
+            })
+            .collect()
+    }
+
+    /// Jaccard similarity weighted by the inverse document frequency of each path and
+    /// directory, from 0 to 1
+    pub fn similarity(&self, other: &PathSet, frequencies: &PathFrequencies) -> f32 {
+        let (a, b) = (self.elements(), other.elements());
+        let idf = |element: &&str| frequencies.idf(element);
+        let union = a.union(&b).map(idf).sum::<f32>();
+        match union > 0.0 {
+            true => a.intersection(&b).map(idf).sum::<f32>() / union,
+            false => 0.0,
+        }
+    }
+}
+
+/// How many PRs touch each path and directory, e.g. the stored PRs of the queried scope
+#[derive(Debug, Clone, Default)]
+pub struct PathFrequencies {
+    /// PRs counted
+    documents: usize,
+    counts: HashMap<String, usize>,
+}
+
+impl PathFrequencies {
+    pub fn new<'a>(sets: impl IntoIterator<Item = &'a PathSet>) -> Self {
+        let mut frequencies = Self::default();
+        for set in sets {
+            frequencies.documents += 1;
+            for element in set.elements() {
+                *frequencies.counts.entry(element.to_string()).or_default() += 1;
+            }
+        }
+        frequencies
+    }
+
+    /// Smoothed inverse document frequency, 1 for what every PR touches and more the fewer
+    /// do. Without any PR counted everything weighs 1
+    fn idf(&self, element: &str) -> f32 {
+        let count = self.counts.get(element).copied().unwrap_or(0);
+        ((1 + self.documents) as f32 / (1 + count) as f32).ln() + 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(paths: &[&str]) -> PathSet {
+        PathSet {
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn shared_directories_weigh_their_rarity() {
+        let sets = [
+            set(&["src/a.rs"]),
+            set(&["src/b.rs"]),
+            set(&["src/c.rs"]),
+            set(&["src/parser/x.rs"]),
+            set(&["src/parser/y.rs"]),
+        ];
+        let frequencies = PathFrequencies::new(&sets);
+
+        // every PR touches src/, only two src/parser/
+        assert!(
+            sets[3].similarity(&sets[4], &frequencies) > sets[0].similarity(&sets[1], &frequencies)
+        );
+        assert_eq!(frequencies.idf("src/"), 1.0);
+    }
+
+    #[test]
+    fn uncounted_paths_weigh_the_same() {
+        let frequencies = PathFrequencies::default();
+
+        // only src/ is shared, out of src/, src/a.rs and src/b.rs
+        assert_eq!(
+            set(&["src/a.rs"]).similarity(&set(&["src/b.rs"]), &frequencies),
+            1.0 / 3.0
+        );
+        assert_eq!(
+            set(&["README.md"]).similarity(&set(&["src/b.rs"]), &frequencies),
+            0.0
+        );
+        assert_eq!(
+            set(&["src/a.rs", "docs/a.md"])
+                .similarity(&set(&["docs/a.md", "src/a.rs"]), &frequencies),
+            1.0
+        );
+    }
+}
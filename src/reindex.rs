@@ -0,0 +1,171 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use log::{info, warn};
+
+use crate::{
+    backfill::embed_pulls,
+    bert::Embedder,
+    content::build_issue_content,
+    error::{self, Error},
+    github::GitHub,
+    http::HttpClient,
+    id::{is_legacy, ItemId, ItemKind},
+    utils::{EmbeddedItem, VectorDB},
+};
+
+/// Re-embeds every stored PR and issue vector that wasn't produced by the current model and
+/// pipeline
+pub struct Reindex<'a, DB: VectorDB> {
+    /// only re-embed this repo's PRs and issues (owner/name)
+    pub repo_name: Option<&'a str>,
+    pub batch_size: usize,
+    /// model to re-embed with
+    pub embedder: &'a Embedder,
+    pub vector_db: &'a DB,
+    pub http: &'a Arc<HttpClient>,
+}
+
+impl<DB: VectorDB> Reindex<'_, DB> {
+    pub async fn run(&self) -> error::Result<()> {
+        let version = self.embedder.version();
+
+        let stored = self
+            .vector_db
+            .list_embeddings()
+            .await
+            .map_err(Error::Backend)?;
+        let total = stored.len();
+
+        let github = GitHub::new(self.http.clone()).map_err(Error::Config)?;
+
+        // group stale numbers by repo and kind, so items are fetched and upserted per repo
+        let mut stale: BTreeMap<(String, ItemKind), Vec<u64>> = BTreeMap::new();
+        for embedding in stored
+            .into_iter()
+            .filter(|e| e.version.as_ref() != Some(&version))
+        {
+            // re-embedding would store the PR under its new id next to the legacy one
+            if is_legacy(&embedding.id) {
+                warn!(
+                    "skipping {}, run `migrate-ids` before reindexing legacy ids",
+                    embedding.id
+                );
+                continue;
+            }
+            let item = match ItemId::from_stored(&embedding.id) {
+                Ok(item) if item.host == github.host() => item,
+                Ok(item) => {
+                    warn!("skipping {item}, it's not on {}", github.host());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("skipping {} | Reason {e}", embedding.id);
+                    continue;
+                }
+            };
+            if self.repo_name.is_some_and(|r| r != item.repo_name()) {
+                continue;
+            }
+            stale
+                .entry((item.repo_name(), item.kind))
+                .or_default()
+                .push(item.number);
+        }
+
+        let stale_count = stale.values().map(Vec::len).sum::<usize>();
+        info!("{stale_count}/{total} stored embeddings are stale | current version {version:?}");
+
+        let mut done = 0;
+        // items that keep their stale vector, e.g. deleted on GitHub or touching a binary file
+        let mut failed = vec![];
+
+        for ((repo_name, kind), numbers) in &stale {
+            for batch in numbers.chunks(self.batch_size.max(1)) {
+                let embeddings = match kind {
+                    ItemKind::Pull => self.embed_pulls(&github, repo_name, batch).await?,
+                    ItemKind::Issue => self.embed_issues(&github, repo_name, batch).await?,
+                };
+                self.vector_db
+                    .save_embeddings(&embeddings, &version)
+                    .await
+                    .map_err(Error::Backend)?;
+
+                failed.extend(
+                    batch
+                        .iter()
+                        .filter(|&&n| !embeddings.iter().any(|e| e.id.number == n))
+                        .map(|n| format!("{repo_name}#{n}")),
+                );
+                done += embeddings.len();
+                info!("reindexed {done}/{stale_count} PRs and issues");
+            }
+        }
+
+        if !failed.is_empty() {
+            warn!(
+                "{} PRs and issues couldn't be reindexed and keep their stale embedding: {}",
+                failed.len(),
+                failed.join(", ")
+            );
+        }
+        info!("finished reindexing");
+        Ok(())
+    }
+
+    async fn embed_pulls(
+        &self,
+        github: &GitHub,
+        repo_name: &str,
+        pr_numbers: &[u64],
+    ) -> error::Result<Vec<EmbeddedItem>> {
+        let mut pulls = Vec::with_capacity(pr_numbers.len());
+        for &pr_number in pr_numbers {
+            match github.pull(repo_name, pr_number).await {
+                Ok(pr) => pulls.push(pr),
+                Err(e) => warn!("skipping {repo_name}#{pr_number} | Reason {e}"),
+            }
+        }
+        embed_pulls(github, self.http, self.embedder, repo_name, &pulls).await
+    }
+
+    /// Embeds the issues' current title, body and labels the way the issues run does
+    async fn embed_issues(
+        &self,
+        github: &GitHub,
+        repo_name: &str,
+        issue_numbers: &[u64],
+    ) -> error::Result<Vec<EmbeddedItem>> {
+        let mut issues = Vec::with_capacity(issue_numbers.len());
+        for &issue_number in issue_numbers {
+            let issue = github
+                .issue(repo_name, issue_number)
+                .await
+                .and_then(|issue| {
+                    let id = ItemId::new(github.host(), repo_name, ItemKind::Issue, issue.number)?;
+                    let labels = issue.labels.into_iter().map(|l| l.name).collect::<Vec<_>>();
+                    let body = issue.body.unwrap_or_default();
+                    Ok((id, build_issue_content(&issue.title, &body, &labels)))
+                });
+            match issue {
+                Ok(issue) => issues.push(issue),
+                Err(e) => warn!("skipping {repo_name}#{issue_number} | Reason {e}"),
+            }
+        }
+
+        let embeddings = self
+            .embedder
+            .embed_batch(
+                &issues
+                    .iter()
+                    .map(|(_, content)| content.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .await
+            .map_err(Error::Model)?;
+        Ok(issues
+            .into_iter()
+            .zip(embeddings)
+            .map(|((id, content), embedding)| EmbeddedItem::new(id, &content, embedding))
+            .collect())
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use anyhow::{Error as E, Result};
+use candle_core::{IndexOp, Tensor};
+use candle_nn::{linear, Linear, Module};
+use log::info;
+use serde::Deserialize;
+use tokenizers::{Tokenizer, TruncationParams, TruncationStrategy};
+
+use crate::{
+    bert::Bert,
+    encoder::{Encoder, EncoderConfig},
+    SimilarPRs,
+};
+
+/// max tokens of a (PR, candidate) pair, the limit of BERT's position embeddings
+const MAX_PAIR_TOKENS: usize = 512;
+
+/// The classification head's labels
+#[derive(Deserialize)]
+struct HeadConfig {
+    #[serde(default)]
+    id2label: HashMap<String, String>,
+}
+
+/// BERT or RoBERTa cross-encoder scoring how similar two texts are by reading them together, which is
+/// slower but much more precise than comparing their embeddings. Used to rescore the few
+/// candidates the vector db returns
+pub struct CrossEncoder {
+    model_id: String,
+    model: Encoder,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+}
+
+impl CrossEncoder {
+    /// default model, a BERT cross-encoder trained on semantic textual similarity
+    pub const MODEL_ID: &'static str = "cross-encoder/stsb-TinyBERT-L-4";
+    pub const REVISION: &'static str = "main";
+
+    /// Loads the model from the same `hub/` cache as [`Bert::build_model_and_tokenizer`]
+    pub async fn load(model_id: &str, revision: &str) -> Result<Self> {
+        let device = Bert::device();
+
+        let (config_filename, tokenizer_filename, weights_filename) =
+            Bert::cached_files(model_id, revision)?;
+        let config = std::fs::read_to_string(config_filename)?;
+        let mut config: serde_json::Value = serde_json::from_str(&config)?;
+        let labels = HeadConfig::deserialize(&config)?.id2label.len().max(1);
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        tokenizer
+            .with_padding(None)
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_PAIR_TOKENS,
+                strategy: TruncationStrategy::LongestFirst,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+
+        let vb = Bert::var_builder(&mut config, &weights_filename, &device)?;
+        let config: EncoderConfig = serde_json::from_value(config)?;
+        let model = Encoder::load(vb.clone(), &config)?;
+
+        // BERT pools the CLS token with `pooler.dense`, stored with or without the model type's
+        // prefix like the encoder, and classifies it. RoBERTa has no pooler, its classification
+        // head projects the CLS token with `classifier.dense` then `classifier.out_proj`
+        let hidden = config.hidden_size;
+        let pooler =
+            linear(hidden, hidden, vb.pp("pooler.dense")).or_else(|e| match &config.model_type {
+                Some(model_type) => {
+                    linear(hidden, hidden, vb.pp(model_type).pp("pooler.dense")).map_err(|_| e)
+                }
+                None => Err(e),
+            });
+        let (pooler, classifier) = match pooler {
+            Ok(pooler) => (pooler, linear(hidden, labels, vb.pp("classifier"))?),
+            Err(e) => (
+                linear(hidden, hidden, vb.pp("classifier.dense")).map_err(|_| e)?,
+                linear(hidden, labels, vb.pp("classifier.out_proj"))?,
+            ),
+        };
+
+        Ok(Self {
+            model_id: model_id.to_string(),
+            model,
+            pooler,
+            classifier,
+            tokenizer,
+        })
+    }
+
+    /// How similar both texts are, from 0 to 1
+    pub fn score(&self, a: &str, b: &str) -> Result<f32> {
+        let encoding = self.tokenizer.encode((a, b), true).map_err(E::msg)?;
+        let device = &self.model.device;
+        let token_ids = Tensor::new(encoding.get_ids(), device)?.unsqueeze(0)?;
+        let token_type_ids = Tensor::new(encoding.get_type_ids(), device)?.unsqueeze(0)?;
+
+        let hidden = self
+            .model
+            .forward(&token_ids, &token_type_ids, &token_ids.ones_like()?)?;
+        let pooled = self.pooler.forward(&hidden.i((.., 0))?)?.tanh()?;
+        let logits = self
+            .classifier
+            .forward(&pooled)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+
+        Ok(match logits[..] {
+            [logit] => 1.0 / (1.0 + (-logit).exp()),
+            // softmax, the last label being "duplicate"
+            _ => {
+                let max = logits.iter().copied().fold(f32::MIN, f32::max);
+                let exps = logits.iter().map(|l| (l - max).exp()).collect::<Vec<_>>();
+                exps[exps.len() - 1] / exps.iter().sum::<f32>()
+            }
+        })
+    }
+
+    /// Rescores every candidate that has a stored summary against `text`, most similar first.
+    /// Exact duplicates keep their 100% and candidates stored before summaries were added keep
+    /// their retrieval score
+    pub fn rerank(&self, text: &str, mut similar: SimilarPRs) -> Result<SimilarPRs> {
+        let start = std::time::Instant::now();
+
+        for candidate in similar.data.iter_mut().filter(|c| !c.exact) {
+            let Some(summary) = &candidate.summary else {
+                continue;
+            };
+            let score = self.score(text, summary)? * 100.0;
+            candidate.rerank = Some(score);
+            candidate.percentage = score;
+        }
+        similar
+            .data
+            .sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+
+        info!(
+            "reranked {} candidates with {} in {:?}",
+            similar.data.len(),
+            self.model_id,
+            start.elapsed()
+        );
+        Ok(similar)
+    }
+}
@@ -0,0 +1,179 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::{
+    github::GitHub,
+    id::{IdError, ItemId},
+    SimilarPRs,
+};
+
+/// Which repos' PRs and issues a query may match
+///
+/// ```
+/// use pr_dedupe::{id::ItemKind, scope::Scope, ItemId};
+///
+/// let pr = ItemId::new("github.com", "acme/app", ItemKind::Pull, 1).unwrap();
+/// let sibling = ItemId::new("github.com", "acme/lib", ItemKind::Pull, 7).unwrap();
+///
+/// assert!(!"repo".parse::<Scope>().unwrap().includes(&pr, &sibling));
+/// assert!("org".parse::<Scope>().unwrap().includes(&pr, &sibling));
+/// assert!("acme/lib,fork/app".parse::<Scope>().unwrap().includes(&pr, &sibling));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// only the PR's own repo
+    #[default]
+    Repo,
+    /// every repo owned by the PR's org or user
+    Org,
+    /// the PR's own repo and these ones (owner/name)
+    Repos(Vec<String>),
+}
+
+impl Scope {
+    /// Whether `candidate` may be reported as similar to `pr`
+    pub fn includes(&self, pr: &ItemId, candidate: &ItemId) -> bool {
+        if candidate.same_repo(pr) {
+            return true;
+        }
+        if candidate.host != pr.host {
+            return false;
+        }
+        match self {
+            Scope::Repo => false,
+            Scope::Org => candidate.owner == pr.owner,
+            Scope::Repos(repos) => repos.contains(&candidate.repo_name()),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = IdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "repo" => Ok(Scope::Repo),
+            "org" => Ok(Scope::Org),
+            repos => repos
+                .split(',')
+                .map(str::trim)
+                .filter(|repo| !repo.is_empty())
+                .map(|repo| match repo.split_once('/') {
+                    Some((owner, name))
+                        if !owner.is_empty() && !name.is_empty() && !name.contains('/') =>
+                    {
+                        Ok(repo.to_string())
+                    }
+                    _ => Err(IdError::InvalidRepo(repo.to_string())),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|repos| match repos.is_empty() {
+                    true => Err(IdError::InvalidRepo(s.to_string())),
+                    false => Ok(Scope::Repos(repos)),
+                }),
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Repo => write!(f, "repo"),
+            Scope::Org => write!(f, "org"),
+            Scope::Repos(repos) => write!(f, "{}", repos.join(",")),
+        }
+    }
+}
+
+/// Drops matches from repos that can't be named in the PR's comments.
+///
+/// A private repo is only disclosed to other private repos of the same owner, and repos the
+/// token can't see at all are never disclosed
+pub struct Visibility {
+    github: GitHub,
+    /// whether each repo is private, `None` when the token can't see it
+    private: HashMap<String, Option<bool>>,
+}
+
+impl Visibility {
+    pub fn new(github: GitHub) -> Self {
+        Self {
+            github,
+            private: HashMap::new(),
+        }
+    }
+
+    async fn is_private(&mut self, repo_name: &str) -> Result<Option<bool>> {
+        if let Some(&private) = self.private.get(repo_name) {
+            return Ok(private);
+        }
+        let private = self.github.repo(repo_name).await?.map(|repo| repo.private);
+        self.private.insert(repo_name.to_string(), private);
+        Ok(private)
+    }
+
+    pub async fn disclosable(&mut self, pr: &ItemId, similar: SimilarPRs) -> Result<SimilarPRs> {
+        // a repo we can't look up is treated as public, so nothing private leaks into it
+        let pr_is_private = self.is_private(&pr.repo_name()).await?.unwrap_or(false);
+
+        let mut data = Vec::with_capacity(similar.data.len());
+        let mut hidden = 0;
+
+        for matched in similar.data {
+            let Ok(id) = ItemId::from_url(&matched.pr_url) else {
+                warn!("skipping {}, it isn't a PR or issue url", matched.pr_url);
+                continue;
+            };
+            if id.same_repo(pr) {
+                data.push(matched);
+                continue;
+            }
+
+            let disclosable = match self.is_private(&id.repo_name()).await? {
+                Some(false) => true,
+                Some(true) => pr_is_private && id.owner == pr.owner,
+                None => false,
+            };
+            match disclosable {
+                true => data.push(matched),
+                false => hidden += 1,
+            }
+        }
+
+        if hidden > 0 {
+            info!("hid {hidden} matches from repos that can't be disclosed in {pr}");
+        }
+
+        Ok(SimilarPRs { data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::ItemKind;
+
+    #[test]
+    fn parses_scopes() {
+        assert_eq!("repo".parse::<Scope>(), Ok(Scope::Repo));
+        assert_eq!(
+            " acme/lib, fork/app ".parse::<Scope>(),
+            Ok(Scope::Repos(vec!["acme/lib".into(), "fork/app".into()]))
+        );
+        assert_eq!(
+            "acme".parse::<Scope>(),
+            Err(IdError::InvalidRepo("acme".into()))
+        );
+    }
+
+    #[test]
+    fn never_includes_other_hosts() {
+        let pr = ItemId::new("github.com", "acme/app", ItemKind::Pull, 1).unwrap();
+        let enterprise = ItemId::new("ghe.acme.com", "acme/lib", ItemKind::Pull, 2).unwrap();
+
+        assert!(!Scope::Org.includes(&pr, &enterprise));
+        assert!(!Scope::Repos(vec!["acme/lib".into()]).includes(&pr, &enterprise));
+    }
+}
@@ -7,10 +7,12 @@ use serde_json::json;
 
 use anyhow::Result;
 
+#[allow(dead_code)]
 struct SB {
     client: Postgrest,
 }
 
+#[allow(dead_code)]
 impl SB {
     pub fn new() -> Result<Self> {
         let (supabase_url, supabase_service_role_key) = (
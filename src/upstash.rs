@@ -1,26 +1,69 @@
-use std::env;
+use std::{collections::HashSet, env, sync::Arc};
 
 use anyhow::{bail, Result};
 
 use log::info;
-use reqwest::{header, Client, Url};
+use rand::seq::SliceRandom;
+use reqwest::{header, Url};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
-    utils::{uuid, uuid_to_pr_number, uuid_to_repo_name, VectorDB},
+    bert::EmbeddingVersion,
+    fingerprint::Fingerprint,
+    http::HttpClient,
+    id::{ItemId, ItemKind},
+    minhash::LexicalSignature,
+    paths::{PathFrequencies, PathSet},
+    utils::{EmbeddedItem, QueryOptions, StoredEmbedding, VectorDB},
     SimilarPRs, SimilarPRsInner,
 };
 
+/// Max number of vectors upstash returns per range request
+const RANGE_LIMIT: u16 = 1000;
+
 pub struct Upstash {
-    client: Client,
+    client: Arc<HttpClient>,
+    /// the REST token, added to each request
+    headers: header::HeaderMap,
     url_endpoint: Url,
 }
 
+/// stored next to every vector
+#[derive(Serialize, Deserialize, Debug)]
+struct Metadata {
+    #[serde(flatten)]
+    version: EmbeddingVersion,
+    /// `None` for vectors stored before fingerprints were, or items without content
+    #[serde(flatten)]
+    fingerprint: Option<Fingerprint>,
+    #[serde(flatten)]
+    lexical: Option<LexicalSignature>,
+    /// `None` for vectors stored before paths were, or items without files
+    #[serde(flatten)]
+    paths: Option<PathSet>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+}
+
+impl Metadata {
+    fn new(item: &EmbeddedItem, version: &EmbeddingVersion) -> Self {
+        Self {
+            version: version.clone(),
+            fingerprint: item.fingerprint.clone(),
+            lexical: item.lexical.clone(),
+            paths: item.paths.clone(),
+            summary: Some(item.summary.clone()).filter(|s| !s.is_empty()),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Data {
     id: String,
     score: f32,
+    #[serde(default)]
+    metadata: Option<Metadata>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,39 +71,233 @@ struct QueryResult {
     result: Vec<Data>,
 }
 
-impl From<QueryResult> for SimilarPRs {
-    fn from(val: QueryResult) -> Self {
-        let repo_name = env::var("REPO_NAME").unwrap();
+#[derive(Serialize, Deserialize, Debug)]
+struct RangeVector {
+    id: String,
+    #[serde(default)]
+    metadata: Option<Metadata>,
+}
 
-        // ask upstash team to provide feature using api?
-        let add_pr_prefix =
-            |pr_number: &str| format!("https://github.com/{}/pull/{pr_number}", &repo_name,);
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RangePage {
+    next_cursor: String,
+    vectors: Vec<RangeVector>,
+}
 
-        let curr_pr = add_pr_prefix(&env::var("PR_NUMBER").unwrap());
+#[derive(Serialize, Deserialize, Debug)]
+struct RangeResult {
+    result: RangePage,
+}
 
-        SimilarPRs {
-            data: val
-                .result
-                .iter()
-                //
-                .filter(|d| {
-                    repo_name == uuid_to_repo_name(&d.id)
-                        && add_pr_prefix(uuid_to_pr_number(&d.id)) != curr_pr
-                })
-                .map(|d| {
-                    let pr_number = uuid_to_pr_number(&d.id);
-                    SimilarPRsInner {
-                        pr_url: add_pr_prefix(pr_number),
-                        percentage: d.score * 100.0,
-                    }
-                })
-                .collect::<Vec<_>>(),
-        }
+#[derive(Serialize, Deserialize, Debug)]
+struct FetchedVector {
+    id: String,
+    vector: Vec<f32>,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FetchResult {
+    /// `None` for ids that don't exist
+    result: Vec<Option<FetchedVector>>,
+}
+
+/// PRs live in the default namespace, issues in their own so they can be queried separately
+fn namespace(kind: ItemKind) -> &'static str {
+    match kind {
+        ItemKind::Pull => "",
+        ItemKind::Issue => "issues",
+    }
+}
+
+/// upstash metadata filter matching vectors produced by `version`
+fn version_filter(version: &EmbeddingVersion) -> String {
+    format!(
+        "model_id = '{}' AND revision = '{}' AND pipeline_version = {}",
+        version.model_id, version.revision, version.pipeline_version
+    )
+}
+
+impl QueryResult {
+    /// Matches from the repos `options` include, without `item` itself, most similar first.
+    /// Scores blend the cosine, lexical and path similarity, except for matches with the same
+    /// content hash as `item`, which are exact duplicates and always 100% similar
+    fn into_similar_prs(
+        self,
+        item: &EmbeddedItem,
+        options: &QueryOptions,
+        path_frequencies: &PathFrequencies,
+    ) -> SimilarPRs {
+        let pr = &item.id;
+        let weights = options.weights_for(pr.kind);
+        let mut data = self
+            .result
+            .iter()
+            .filter_map(|d| Some((ItemId::from_stored(&d.id).ok()?, d)))
+            // ask upstash team to provide feature using api?
+            .filter(|(id, _)| options.includes(pr, id) && id != pr)
+            .map(|(id, d)| {
+                let metadata = d.metadata.as_ref();
+                let score = item.score(
+                    d.score,
+                    metadata.and_then(|m| m.fingerprint.as_ref()),
+                    metadata.and_then(|m| m.lexical.as_ref()),
+                    metadata.and_then(|m| m.paths.as_ref()),
+                    path_frequencies,
+                    &weights,
+                );
+                SimilarPRsInner {
+                    pr_url: id.url(),
+                    percentage: score.percentage,
+                    repo: (!id.same_repo(pr)).then(|| id.repo_name()),
+                    same_patch: options.is_same_patch(pr, &id, score.percentage),
+                    exact: score.exact,
+                    identical_files: score.identical_files,
+                    lexical: score.lexical.map(|l| l * 100.0),
+                    paths: score.paths.map(|p| p * 100.0),
+                    rerank: None,
+                    summary: metadata.and_then(|m| m.summary.clone()),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        data.sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+        // exact duplicates are usually returned by both the semantic and the hash query
+        let mut seen = HashSet::new();
+        data.retain(|d| seen.insert(d.pr_url.clone()));
+
+        SimilarPRs { data }
     }
 }
 
 impl Upstash {
-    pub fn new() -> Result<Self> {
+    /// `action`'s endpoint in the namespace storing `kind`
+    fn endpoint(&self, action: &str, kind: ItemKind) -> Result<Url> {
+        Ok(match namespace(kind) {
+            "" => self.url_endpoint.join(action)?,
+            namespace => self.url_endpoint.join(&format!("{action}/{namespace}"))?,
+        })
+    }
+
+    /// Runs a query against the namespace storing `collection`
+    async fn query_namespace(
+        &self,
+        collection: ItemKind,
+        embedding: &[f32],
+        top_k: u8,
+        filter: String,
+    ) -> Result<Vec<Data>> {
+        let data = json!({
+            "topK": top_k,
+            "vector": &embedding,
+            "includeMetadata": true,
+            "filter": filter,
+        })
+        .to_string();
+
+        let uri = self.endpoint("query", collection)?;
+
+        let resp = self
+            .client
+            .send(
+                self.client
+                    .post(uri)
+                    .headers(self.headers.clone())
+                    .body(data),
+            )
+            .await?;
+
+        if resp.status().as_u16() != 200 {
+            bail!(
+                "Couldn't query db for similar PRs | Reason {}",
+                resp.text().await.unwrap()
+            );
+        }
+
+        Ok(serde_json::from_str::<QueryResult>(&resp.text().await.unwrap())?.result)
+    }
+
+    /// Every vector stored in the namespace storing `kind`, with its metadata
+    async fn range_namespace(&self, kind: ItemKind) -> Result<Vec<RangeVector>> {
+        let uri = self.endpoint("range", kind)?;
+        let mut cursor = "0".to_string();
+        let mut vectors = Vec::new();
+
+        loop {
+            let data = json!({
+                "cursor": cursor,
+                "limit": RANGE_LIMIT,
+                "includeMetadata": true,
+            })
+            .to_string();
+
+            let resp = self
+                .client
+                .send(
+                    self.client
+                        .post(uri.clone())
+                        .headers(self.headers.clone())
+                        .body(data),
+                )
+                .await?;
+
+            if resp.status().as_u16() != 200 {
+                bail!(
+                    "Couldn't list stored embeddings | Reason {}",
+                    resp.text().await.unwrap()
+                );
+            }
+
+            let page = serde_json::from_str::<RangeResult>(&resp.text().await.unwrap())?.result;
+
+            vectors.extend(page.vectors);
+
+            if page.next_cursor.is_empty() {
+                return Ok(vectors);
+            }
+            cursor = page.next_cursor;
+        }
+    }
+
+    async fn list_namespace(&self, kind: ItemKind) -> Result<Vec<StoredEmbedding>> {
+        Ok(self
+            .range_namespace(kind)
+            .await?
+            .into_iter()
+            .map(|v| StoredEmbedding {
+                id: v.id,
+                version: v.metadata.map(|m| m.version),
+            })
+            .collect())
+    }
+
+    /// How often the paths `item` touches are touched by the stored PRs `options` include.
+    /// Counting them ranges over the whole collection, so it's skipped when paths don't count
+    async fn path_frequencies(
+        &self,
+        item: &EmbeddedItem,
+        options: &QueryOptions,
+        version: &EmbeddingVersion,
+    ) -> Result<PathFrequencies> {
+        if item.paths.is_none() || options.weights_for(item.id.kind).paths <= 0.0 {
+            return Ok(PathFrequencies::default());
+        }
+        let vectors = self.range_namespace(options.collection).await?;
+        Ok(PathFrequencies::new(
+            vectors
+                .iter()
+                .filter(|v| {
+                    ItemId::from_stored(&v.id).is_ok_and(|id| options.includes(&item.id, &id))
+                })
+                .filter_map(|v| v.metadata.as_ref())
+                .filter(|m| &m.version == version)
+                .filter_map(|m| m.paths.as_ref()),
+        ))
+    }
+
+    pub fn new(client: Arc<HttpClient>) -> Result<Self> {
         let (upstash_vector_rest_url, upstash_vector_rest_token) = (
             env::var("UPSTASH_VECTOR_REST_URL"),
             env::var("UPSTASH_VECTOR_REST_TOKEN"),
@@ -82,33 +319,36 @@ impl Upstash {
         let mut headers = header::HeaderMap::new();
         headers.insert(header::AUTHORIZATION, value);
 
-        // initialize reqwest client
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()?;
-
         let url_endpoint = Url::parse(&upstash_vector_rest_url)?;
 
         Ok(Self {
             client,
+            headers,
             url_endpoint,
         })
     }
 }
 
 impl VectorDB for Upstash {
-    async fn save_embedding(&self, embedding: &[f32]) -> Result<()> {
-        let (repo_name, pr_number) = (env::var("REPO_NAME")?, env::var("PR_NUMBER")?);
-
+    async fn save_embedding(&self, item: &EmbeddedItem, version: &EmbeddingVersion) -> Result<()> {
         let data = json!({
-            "id": uuid(&repo_name,&pr_number),
-            "vector": embedding,
+            "id": item.id.to_string(),
+            "vector": item.embedding,
+            "metadata": Metadata::new(item, version),
         })
         .to_string();
 
-        let uri = self.url_endpoint.join("upsert")?;
+        let uri = self.endpoint("upsert", item.id.kind)?;
 
-        let resp = self.client.post(uri).body(data).send().await?;
+        let resp = self
+            .client
+            .send(
+                self.client
+                    .post(uri)
+                    .headers(self.headers.clone())
+                    .body(data),
+            )
+            .await?;
 
         if resp.status().as_u16() != 200 {
             bail!(
@@ -120,56 +360,621 @@ impl VectorDB for Upstash {
         Ok(())
     }
 
-    async fn remove_pr(&self) -> Result<()> {
-        let (repo_name, pr_number) = (env::var("REPO_NAME")?, env::var("PR_NUMBER")?);
+    async fn save_embeddings(
+        &self,
+        items: &[EmbeddedItem],
+        version: &EmbeddingVersion,
+    ) -> Result<()> {
+        for kind in [ItemKind::Pull, ItemKind::Issue] {
+            let vectors = items
+                .iter()
+                .filter(|item| item.id.kind == kind)
+                .map(|item| {
+                    json!({
+                        "id": item.id.to_string(),
+                        "vector": item.embedding,
+                        "metadata": Metadata::new(item, version),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            if vectors.is_empty() {
+                continue;
+            }
+
+            let data = serde_json::Value::Array(vectors).to_string();
+
+            let uri = self.endpoint("upsert", kind)?;
+
+            let resp = self
+                .client
+                .send(
+                    self.client
+                        .post(uri)
+                        .headers(self.headers.clone())
+                        .body(data),
+                )
+                .await?;
+
+            if resp.status().as_u16() != 200 {
+                bail!(
+                    "Couldn't save embeddings | Reason {}",
+                    resp.text().await.unwrap()
+                );
+            }
+        }
 
-        let data = format!("{:?}", [uuid(&repo_name, &pr_number)]);
+        Ok(())
+    }
+
+    async fn remove_embedding(&self, id: &ItemId) -> Result<()> {
+        // PRs stored before the id format changed may still be under their legacy id
+        let data = format!(
+            "{:?}",
+            [Some(id.to_string()), id.legacy_id()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+        );
 
         println!("data {data}");
 
-        let uri = self.url_endpoint.join("delete")?;
+        let uri = self.endpoint("delete", id.kind)?;
 
-        let resp = self.client.delete(uri).body(data).send().await?;
+        let resp = self
+            .client
+            .send(
+                self.client
+                    .delete(uri)
+                    .headers(self.headers.clone())
+                    .body(data),
+            )
+            .await?;
 
         let status = resp.status();
         let resp_data = resp.text().await.unwrap();
 
         if status.as_u16() != 200 {
             bail!(
-                "Couldn't remove PR embedding from vector db | Reason {}",
+                "Couldn't remove {id}'s embedding from vector db | Reason {}",
                 resp_data
             );
         }
-        info!("response data after removing PR from db, {resp_data}");
+        info!("response data after removing {id} from db, {resp_data}");
 
         Ok(())
     }
 
-    async fn query(&self, embedding: &[f32], top_k: u8, min_similarity: u8) -> Result<SimilarPRs> {
+    async fn query(
+        &self,
+        item: &EmbeddedItem,
+        options: &QueryOptions,
+        version: &EmbeddingVersion,
+    ) -> Result<SimilarPRs> {
+        let mut result = self
+            .query_namespace(
+                options.collection,
+                &item.embedding,
+                options.top_k,
+                version_filter(version),
+            )
+            .await?;
+
+        // exact duplicates are looked up by hash, so they're found even when their cosine
+        // score wobbles out of the top k
+        if let Some(fingerprint) = &item.fingerprint {
+            result.extend(
+                self.query_namespace(
+                    options.collection,
+                    &item.embedding,
+                    options.top_k,
+                    format!(
+                        "{} AND content_hash = '{}'",
+                        version_filter(version),
+                        fingerprint.content_hash
+                    ),
+                )
+                .await?,
+            );
+        }
+
+        // lexically close PRs the embedding missed, e.g. the same code with renamed identifiers
+        if let Some(lexical) = &item.lexical {
+            let bands = lexical
+                .lsh_bands
+                .iter()
+                .map(|band| format!("lsh_bands CONTAINS '{band}'"))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            result.extend(
+                self.query_namespace(
+                    options.collection,
+                    &item.embedding,
+                    options.top_k,
+                    format!("{} AND ({bands})", version_filter(version)),
+                )
+                .await?,
+            );
+        }
+
+        // vectors stored before versions were tracked have no metadata to filter on
+        result.retain(|d| d.metadata.as_ref().map(|m| &m.version) == Some(version));
+
+        let path_frequencies = self.path_frequencies(item, options, version).await?;
+        // ask upstash team to provide feature using api?
+        let mut similar_prs =
+            QueryResult { result }.into_similar_prs(item, options, &path_frequencies);
+        similar_prs
+            .data
+            .retain(|d| d.percentage >= options.min_similarity as f32);
+        similar_prs.data.truncate(options.top_k as usize);
+        Ok(similar_prs)
+    }
+
+    async fn list_embeddings(&self) -> Result<Vec<StoredEmbedding>> {
+        let mut embeddings = Vec::new();
+
+        for kind in [ItemKind::Pull, ItemKind::Issue] {
+            embeddings.extend(self.list_namespace(kind).await?);
+        }
+
+        Ok(embeddings)
+    }
+
+    async fn sample_embeddings(
+        &self,
+        repo_name: &str,
+        kind: ItemKind,
+        version: &EmbeddingVersion,
+        limit: usize,
+    ) -> Result<Vec<EmbeddedItem>> {
+        // ranges are cheap without vectors, only the sampled ones are fetched
+        let ids = self
+            .list_namespace(kind)
+            .await?
+            .into_iter()
+            .filter(|e| e.version.as_ref() == Some(version))
+            .filter(|e| {
+                ItemId::from_stored(&e.id)
+                    .is_ok_and(|id| id.kind == kind && id.repo_name() == repo_name)
+            })
+            .map(|e| e.id)
+            .collect::<Vec<_>>();
+        let ids = ids
+            .choose_multiple(&mut rand::thread_rng(), limit)
+            .collect::<Vec<_>>();
+
+        let mut items = Vec::with_capacity(ids.len());
+        for ids in ids.chunks(RANGE_LIMIT as usize) {
+            let data = json!({
+                "ids": ids,
+                "includeVectors": true,
+                "includeMetadata": true,
+            })
+            .to_string();
+
+            let uri = self.endpoint("fetch", kind)?;
+
+            let resp = self
+                .client
+                .send(
+                    self.client
+                        .post(uri)
+                        .headers(self.headers.clone())
+                        .body(data),
+                )
+                .await?;
+
+            if resp.status().as_u16() != 200 {
+                bail!(
+                    "Couldn't fetch sampled embeddings | Reason {}",
+                    resp.text().await.unwrap()
+                );
+            }
+
+            let fetched = serde_json::from_str::<FetchResult>(&resp.text().await.unwrap())?.result;
+            items.extend(fetched.into_iter().flatten().filter_map(|vector| {
+                let metadata = vector
+                    .metadata
+                    .and_then(|m| serde_json::from_value::<Metadata>(m).ok());
+                Some(EmbeddedItem {
+                    id: ItemId::from_stored(&vector.id).ok()?,
+                    embedding: vector.vector,
+                    fingerprint: metadata.as_ref().and_then(|m| m.fingerprint.clone()),
+                    lexical: metadata.as_ref().and_then(|m| m.lexical.clone()),
+                    paths: metadata.as_ref().and_then(|m| m.paths.clone()),
+                    summary: metadata.and_then(|m| m.summary).unwrap_or_default(),
+                })
+            }));
+        }
+
+        Ok(items)
+    }
+
+    async fn rename_embeddings(&self, renames: &[(String, ItemId)]) -> Result<()> {
+        if renames.is_empty() {
+            return Ok(());
+        }
+
         let data = json!({
-            "topK": top_k,
-            "vector": &embedding,
+            "ids": renames.iter().map(|(from, _)| from).collect::<Vec<_>>(),
+            "includeVectors": true,
+            "includeMetadata": true,
         })
         .to_string();
 
-        let uri = self.url_endpoint.join("query")?;
+        // legacy ids were only ever written for PRs
+        let uri = self.endpoint("fetch", ItemKind::Pull)?;
 
-        let resp = self.client.post(uri).body(data).send().await?;
+        let resp = self
+            .client
+            .send(
+                self.client
+                    .post(uri)
+                    .headers(self.headers.clone())
+                    .body(data),
+            )
+            .await?;
 
         if resp.status().as_u16() != 200 {
             bail!(
-                "Couldn't query db for similar PRs | Reason {}",
+                "Couldn't fetch embeddings to rename | Reason {}",
                 resp.text().await.unwrap()
             );
         }
 
-        let results = serde_json::from_str::<QueryResult>(&resp.text().await.unwrap())?;
+        let fetched = serde_json::from_str::<FetchResult>(&resp.text().await.unwrap())?.result;
 
-        // ask upstash team to provide feature using api?
-        let mut similar_prs: SimilarPRs = results.into();
-        similar_prs
-            .data
-            .retain(|d| d.percentage >= min_similarity as f32);
-        Ok(similar_prs)
+        let data = serde_json::Value::Array(
+            fetched
+                .iter()
+                .zip(renames)
+                .filter_map(|(vector, (_, to))| {
+                    let vector = vector.as_ref()?;
+                    Some(json!({
+                        "id": to.to_string(),
+                        "vector": vector.vector,
+                        "metadata": vector.metadata,
+                    }))
+                })
+                .collect(),
+        )
+        .to_string();
+
+        let uri = self.endpoint("upsert", ItemKind::Pull)?;
+
+        let resp = self
+            .client
+            .send(
+                self.client
+                    .post(uri)
+                    .headers(self.headers.clone())
+                    .body(data),
+            )
+            .await?;
+
+        if resp.status().as_u16() != 200 {
+            bail!(
+                "Couldn't save renamed embeddings | Reason {}",
+                resp.text().await.unwrap()
+            );
+        }
+
+        // only drop the old ids once their vectors are safely stored under the new ones
+        let data = format!(
+            "{:?}",
+            fetched
+                .iter()
+                .flatten()
+                .map(|vector| &vector.id)
+                .collect::<Vec<_>>()
+        );
+
+        let uri = self.endpoint("delete", ItemKind::Pull)?;
+
+        let resp = self
+            .client
+            .send(
+                self.client
+                    .delete(uri)
+                    .headers(self.headers.clone())
+                    .body(data),
+            )
+            .await?;
+
+        if resp.status().as_u16() != 200 {
+            bail!(
+                "Couldn't remove renamed embeddings | Reason {}",
+                resp.text().await.unwrap()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{build_issue_content, id::GITHUB_HOST, scope::Scope, utils::ScoreWeights, Bert};
+
+    use super::*;
+
+    fn data(id: &str, score: f32) -> Data {
+        Data {
+            id: id.to_string(),
+            score,
+            metadata: None,
+        }
+    }
+
+    fn item(fingerprint: Option<Fingerprint>) -> EmbeddedItem {
+        EmbeddedItem {
+            id: ItemId::new(GITHUB_HOST, "cs50victor/pr_dedupe", ItemKind::Pull, 2).unwrap(),
+            embedding: vec![],
+            fingerprint,
+            lexical: None,
+            paths: None,
+            summary: String::new(),
+        }
+    }
+
+    #[test]
+    fn query_result_skips_current_pr_and_other_repos() {
+        let result = QueryResult {
+            result: vec![
+                data("github.com/cs50victor/pr_dedupe/pull/2", 1.0),
+                data("cs50victor/pr_dedupe:2", 1.0),
+                data("cs50victor/pr_dedupe:1", 0.9),
+                data("github.com/someone/else/pull/1", 0.95),
+                data("ghe.example.com/cs50victor/pr_dedupe/pull/3", 0.95),
+            ],
+        };
+
+        let similar_prs = result.into_similar_prs(
+            &item(None),
+            &QueryOptions::new(ItemKind::Pull, 10, 80),
+            &PathFrequencies::default(),
+        );
+
+        assert_eq!(similar_prs.data.len(), 1);
+        assert_eq!(
+            similar_prs.data[0].pr_url,
+            "https://github.com/cs50victor/pr_dedupe/pull/1"
+        );
+        assert_eq!(similar_prs.data[0].percentage, 90.0);
+    }
+
+    #[test]
+    fn org_scope_labels_matches_from_other_repos() {
+        let result = QueryResult {
+            result: vec![
+                data("github.com/cs50victor/pr_dedupe/pull/1", 0.9),
+                data("github.com/cs50victor/other/pull/4", 0.85),
+                data("github.com/someone/else/pull/1", 0.95),
+            ],
+        };
+
+        let similar_prs = result.into_similar_prs(
+            &item(None),
+            &QueryOptions {
+                scope: Scope::Org,
+                ..QueryOptions::new(ItemKind::Pull, 10, 80)
+            },
+            &PathFrequencies::default(),
+        );
+
+        assert_eq!(similar_prs.data.len(), 2);
+        assert_eq!(similar_prs.data[0].repo, None);
+        assert_eq!(
+            similar_prs.data[1].pr_url,
+            "https://github.com/cs50victor/other/pull/4"
+        );
+        assert_eq!(
+            similar_prs.data[1].repo.as_deref(),
+            Some("cs50victor/other")
+        );
+    }
+
+    #[test]
+    fn flags_near_exact_matches_from_related_repos() {
+        let result = QueryResult {
+            result: vec![
+                data("github.com/cs50victor/pr_dedupe/pull/1", 0.999),
+                data("github.com/fork/pr_dedupe/pull/9", 0.995),
+                data("github.com/fork/pr_dedupe/pull/8", 0.9),
+                data("github.com/cs50victor/other/pull/2", 0.999),
+                data("github.com/someone/else/pull/1", 0.999),
+            ],
+        };
+
+        let similar_prs = result.into_similar_prs(
+            &item(None),
+            &QueryOptions {
+                scope: Scope::Org,
+                related_repos: vec!["fork/pr_dedupe".into()],
+                ..QueryOptions::new(ItemKind::Pull, 10, 80)
+            },
+            &PathFrequencies::default(),
+        );
+
+        assert_eq!(
+            similar_prs
+                .data
+                .iter()
+                .map(|d| (d.pr_url.as_str(), d.same_patch))
+                .collect::<Vec<_>>(),
+            [
+                ("https://github.com/cs50victor/pr_dedupe/pull/1", false),
+                ("https://github.com/cs50victor/other/pull/2", false),
+                ("https://github.com/fork/pr_dedupe/pull/9", true),
+                ("https://github.com/fork/pr_dedupe/pull/8", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn issues_use_their_own_namespace() {
+        let upstash = Upstash {
+            client: Arc::new(HttpClient::new(reqwest::Client::builder()).unwrap()),
+            headers: header::HeaderMap::new(),
+            url_endpoint: Url::parse("https://example.upstash.io").unwrap(),
+        };
+
+        assert_eq!(
+            upstash.endpoint("query", ItemKind::Pull).unwrap().as_str(),
+            "https://example.upstash.io/query"
+        );
+        assert_eq!(
+            upstash.endpoint("query", ItemKind::Issue).unwrap().as_str(),
+            "https://example.upstash.io/query/issues"
+        );
+    }
+
+    #[test]
+    fn exact_duplicates_are_always_100_percent() {
+        let file = |content: &str| {
+            Fingerprint::new(&[format!(
+                "+ : https://github.com/cs50victor/pr_dedupe/raw/abc/src/lib.rs\n{content}\n"
+            )])
+        };
+        let stored = |id: &str, score: f32, fingerprint: Option<Fingerprint>| Data {
+            metadata: Some(Metadata {
+                version: Bert::new().version(),
+                fingerprint,
+                lexical: None,
+                paths: None,
+                summary: None,
+            }),
+            ..data(id, score)
+        };
+        let result = QueryResult {
+            result: vec![
+                stored("github.com/cs50victor/pr_dedupe/pull/3", 0.75, file("b")),
+                stored("github.com/cs50victor/pr_dedupe/pull/1", 0.95, file("a")),
+                // also returned by the hash lookup
+                stored(
+                    "github.com/cs50victor/pr_dedupe/pull/1",
+                    f32::NAN,
+                    file("a"),
+                ),
+            ],
+        };
+
+        let similar_prs = result.into_similar_prs(
+            &item(file("a")),
+            &QueryOptions::new(ItemKind::Pull, 10, 80),
+            &PathFrequencies::default(),
+        );
+
+        assert_eq!(
+            similar_prs
+                .data
+                .iter()
+                .map(|d| (d.pr_url.as_str(), d.percentage, d.exact))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "https://github.com/cs50victor/pr_dedupe/pull/1",
+                    100.0,
+                    true
+                ),
+                (
+                    "https://github.com/cs50victor/pr_dedupe/pull/3",
+                    75.0,
+                    false
+                ),
+            ]
+        );
+        assert_eq!(similar_prs.data[0].identical_files, ["src/lib.rs"]);
+    }
+
+    #[test]
+    fn blends_cosine_and_lexical_scores() {
+        let content = |code: &str| [format!("+ : src/lib.rs\n{code}\n")];
+        let code = "fn total(values: &[u32]) -> u32 { values.iter().sum() }";
+        let result = QueryResult {
+            result: vec![
+                Data {
+                    metadata: Some(Metadata {
+                        version: Bert::new().version(),
+                        fingerprint: None,
+                        lexical: LexicalSignature::new(&content(code)),
+                        paths: None,
+                        summary: None,
+                    }),
+                    ..data("github.com/cs50victor/pr_dedupe/pull/1", 0.5)
+                },
+                data("github.com/cs50victor/pr_dedupe/pull/3", 0.5),
+            ],
+        };
+
+        let similar_prs = result.into_similar_prs(
+            &EmbeddedItem {
+                lexical: LexicalSignature::new(&content(code)),
+                ..item(None)
+            },
+            &QueryOptions {
+                weights: ScoreWeights {
+                    semantic: 0.5,
+                    lexical: 0.5,
+                    paths: 0.0,
+                },
+                ..QueryOptions::new(ItemKind::Pull, 10, 80)
+            },
+            &PathFrequencies::default(),
+        );
+
+        assert_eq!(similar_prs.data[0].percentage, 75.0);
+        assert_eq!(similar_prs.data[0].lexical, Some(100.0));
+        // stored before lexical signatures were
+        assert_eq!(similar_prs.data[1].percentage, 50.0);
+        assert_eq!(similar_prs.data[1].lexical, None);
+    }
+
+    #[test]
+    fn issues_a_pr_may_fix_are_scored_on_their_embedding() {
+        let code = [
+            "+ : src/lib.rs\nfn parse(input: &str) -> Option<u32> { input.parse().ok() }\n"
+                .to_string(),
+        ];
+        let issue = build_issue_content("Parsing crashes", "on inputs that aren't numbers", &[]);
+        let result = QueryResult {
+            result: vec![Data {
+                metadata: Some(Metadata {
+                    version: Bert::new().version(),
+                    fingerprint: None,
+                    lexical: LexicalSignature::new(&issue),
+                    paths: None,
+                    summary: None,
+                }),
+                ..data("github.com/cs50victor/pr_dedupe/issues/5", 0.9)
+            }],
+        };
+
+        let similar = result.into_similar_prs(
+            &EmbeddedItem {
+                lexical: LexicalSignature::new(&code),
+                ..item(None)
+            },
+            &QueryOptions::new(ItemKind::Issue, 10, 80),
+            &PathFrequencies::default(),
+        );
+
+        assert_eq!(similar.data[0].percentage, 90.0);
+    }
+
+    #[test]
+    fn metadata_fingerprint_is_optional() {
+        let version = serde_json::to_value(Bert::new().version()).unwrap();
+        let old = serde_json::from_value::<Metadata>(version.clone()).unwrap();
+
+        let mut with_hash = version.as_object().unwrap().clone();
+        with_hash.insert("content_hash".into(), "abc".into());
+        with_hash.insert("file_hashes".into(), json!({ "src/lib.rs": "0123" }));
+        let new = serde_json::from_value::<Metadata>(with_hash.into()).unwrap();
+
+        assert_eq!(old.fingerprint, None);
+        assert_eq!(old.version, Bert::new().version());
+        assert_eq!(new.fingerprint.unwrap().content_hash, "abc");
     }
 }
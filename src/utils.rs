@@ -3,36 +3,296 @@ use std::{
     fs::File,
     io::{self, Write},
     path::Path,
-    process::exit,
 };
 
-use log::error;
-
 use anyhow::Result;
 
-use crate::SimilarPRs;
+use crate::{
+    bert::EmbeddingVersion,
+    content::summarize,
+    fingerprint::Fingerprint,
+    id::{ItemId, ItemKind},
+    minhash::LexicalSignature,
+    paths::{PathFrequencies, PathSet},
+    scope::Scope,
+    SimilarPRs,
+};
 
-pub trait VectorDB {
-    async fn save_embedding(&self, embedding: &[f32]) -> Result<()>;
-    async fn remove_pr(&self) -> Result<()>;
-    async fn query(&self, embedding: &[f32], top_k: u8, min_similarity: u8) -> Result<SimilarPRs>;
+/// What [`VectorDB::query`] looks for
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    /// whether to match stored PRs or issues
+    pub collection: ItemKind,
+    pub scope: Scope,
+    /// forks or upstreams of the queried PR's repo (owner/name), always queried on top of `scope`
+    pub related_repos: Vec<String>,
+    pub top_k: u8,
+    /// minimum similarity, in percent
+    pub min_similarity: u8,
+    /// similarity, in percent, from which a match from a related repo counts as the same patch
+    pub same_patch_similarity: u8,
+    pub weights: ScoreWeights,
+}
+
+/// How much the embeddings' cosine similarity, the lexical similarity and the touched paths'
+/// similarity weigh in a match's score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreWeights {
+    pub semantic: f32,
+    pub lexical: f32,
+    pub paths: f32,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            semantic: 0.7,
+            lexical: 0.3,
+            paths: 0.2,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// Weighted average of the scores there are, just the semantic one when there's no other
+    ///
+    /// ```
+    /// use pr_dedupe::utils::ScoreWeights;
+    ///
+    /// let weights = ScoreWeights { semantic: 0.5, lexical: 0.5, paths: 1.0 };
+    /// assert_eq!(weights.blend(0.9, Some(0.5), None), 0.7);
+    /// assert_eq!(weights.blend(0.9, None, Some(0.3)), 0.5);
+    /// assert_eq!(weights.blend(0.9, None, None), 0.9);
+    /// ```
+    pub fn blend(&self, semantic: f32, lexical: Option<f32>, paths: Option<f32>) -> f32 {
+        let (mut sum, mut total) = (self.semantic * semantic, self.semantic);
+        for (weight, score) in [(self.lexical, lexical), (self.paths, paths)] {
+            if let Some(score) = score {
+                sum += weight * score;
+                total += weight;
+            }
+        }
+        match total > 0.0 {
+            true => sum / total,
+            false => semantic,
+        }
+    }
+}
+
+impl QueryOptions {
+    /// matches within the queried PR's repo
+    pub fn new(collection: ItemKind, top_k: u8, min_similarity: u8) -> Self {
+        Self {
+            collection,
+            scope: Scope::Repo,
+            related_repos: vec![],
+            top_k,
+            min_similarity,
+            same_patch_similarity: 99,
+            weights: ScoreWeights::default(),
+        }
+    }
+
+    /// The weights matches of an `item_kind` item are scored with. Code and issue prose share
+    /// next to no tokens or paths, so matches from the other collection are scored on their
+    /// embeddings alone
+    pub fn weights_for(&self, item_kind: ItemKind) -> ScoreWeights {
+        match self.collection == item_kind {
+            true => self.weights,
+            false => ScoreWeights {
+                semantic: 1.0,
+                lexical: 0.0,
+                paths: 0.0,
+            },
+        }
+    }
+
+    /// Whether `candidate` may be reported as similar to `pr`
+    pub fn includes(&self, pr: &ItemId, candidate: &ItemId) -> bool {
+        self.scope.includes(pr, candidate)
+            || (candidate.host == pr.host && self.related_repos.contains(&candidate.repo_name()))
+    }
+
+    /// Whether the match is a near-exact copy of `pr` living in one of the related repos.
+    /// Other repos in scope, e.g. the org's, hold different projects rather than copies
+    pub fn is_same_patch(&self, pr: &ItemId, candidate: &ItemId, percentage: f32) -> bool {
+        !candidate.same_repo(pr)
+            && candidate.host == pr.host
+            && self.related_repos.contains(&candidate.repo_name())
+            && percentage >= self.same_patch_similarity as f32
+    }
 }
 
-pub fn uuid(repo_name: &str, pr_number: &str) -> String {
-    format!("{repo_name}:{pr_number}")
+/// A PR's or issue's embedding, and the fingerprint, lexical signature, touched paths and
+/// summary of the content it was computed from
+#[derive(Debug, Clone)]
+pub struct EmbeddedItem {
+    pub id: ItemId,
+    pub embedding: Vec<f32>,
+    pub fingerprint: Option<Fingerprint>,
+    pub lexical: Option<LexicalSignature>,
+    pub paths: Option<PathSet>,
+    /// see [`summarize`]
+    pub summary: String,
 }
 
-pub fn uuid_to_pr_number(uuid: &str) -> &str {
-    uuid.split(':').next_back().unwrap()
+impl EmbeddedItem {
+    pub fn new(id: ItemId, content: &[String], embedding: Vec<f32>) -> Self {
+        Self {
+            id,
+            embedding,
+            fingerprint: Fingerprint::new(content),
+            lexical: LexicalSignature::new(content),
+            paths: PathSet::new(content),
+            summary: summarize(content),
+        }
+    }
+
+    /// Scores a stored PR or issue against this one, `semantic` being the vector db's
+    /// similarity from 0 to 1 and `path_frequencies` counting the paths of the PRs it's
+    /// compared among. Exact duplicates always score 100%
+    pub fn score(
+        &self,
+        semantic: f32,
+        fingerprint: Option<&Fingerprint>,
+        lexical: Option<&LexicalSignature>,
+        paths: Option<&PathSet>,
+        path_frequencies: &PathFrequencies,
+        weights: &ScoreWeights,
+    ) -> MatchScore {
+        let (exact, identical_files) = match (&self.fingerprint, fingerprint) {
+            (Some(fingerprint), Some(stored)) => (
+                fingerprint.content_hash == stored.content_hash,
+                fingerprint.identical_files(stored),
+            ),
+            _ => (false, vec![]),
+        };
+        let lexical = self
+            .lexical
+            .as_ref()
+            .zip(lexical)
+            .map(|(lexical, stored)| lexical.similarity(stored));
+        let paths = self
+            .paths
+            .as_ref()
+            .zip(paths)
+            .map(|(paths, stored)| paths.similarity(stored, path_frequencies));
+        MatchScore {
+            percentage: match exact {
+                true => 100.0,
+                false => weights.blend(semantic, lexical, paths) * 100.0,
+            },
+            exact,
+            identical_files,
+            lexical,
+            paths,
+        }
+    }
 }
 
-pub fn uuid_to_repo_name(uuid: &str) -> &str {
-    uuid.split(':').next().unwrap()
+/// See [`EmbeddedItem::score`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchScore {
+    pub percentage: f32,
+    pub exact: bool,
+    pub identical_files: Vec<String>,
+    /// lexical similarity from 0 to 1, when both have a signature
+    pub lexical: Option<f32>,
+    /// touched paths' similarity from 0 to 1, when both touch files
+    pub paths: Option<f32>,
 }
 
-pub fn log_err_and_exit(msg: impl AsRef<str>) -> ! {
-    error!("{}", msg.as_ref());
-    exit(1);
+#[derive(Debug)]
+pub struct StoredEmbedding {
+    /// raw id, which may still be in the legacy format, see [`ItemId::from_stored`]
+    pub id: String,
+    /// `None` for vectors stored before versions were tracked
+    pub version: Option<EmbeddingVersion>,
+}
+
+/// Stores PR and issue embeddings and finds the ones closest to a new PR or issue
+///
+/// ```
+/// use anyhow::Result;
+/// use pr_dedupe::{
+///     bert::EmbeddingVersion,
+///     id::{ItemId, ItemKind},
+///     utils::{EmbeddedItem, QueryOptions, StoredEmbedding},
+///     SimilarPRs, VectorDB,
+/// };
+///
+/// /// keeps nothing and never finds a match
+/// struct NoopDB;
+///
+/// impl VectorDB for NoopDB {
+///     async fn save_embedding(&self, _: &EmbeddedItem, _: &EmbeddingVersion) -> Result<()> {
+///         Ok(())
+///     }
+///     async fn save_embeddings(&self, _: &[EmbeddedItem], _: &EmbeddingVersion) -> Result<()> {
+///         Ok(())
+///     }
+///     async fn remove_embedding(&self, _: &ItemId) -> Result<()> {
+///         Ok(())
+///     }
+///     async fn query(
+///         &self,
+///         _: &EmbeddedItem,
+///         _: &QueryOptions,
+///         _: &EmbeddingVersion,
+///     ) -> Result<SimilarPRs> {
+///         Ok(SimilarPRs { data: vec![] })
+///     }
+///     async fn list_embeddings(&self) -> Result<Vec<StoredEmbedding>> {
+///         Ok(vec![])
+///     }
+///     async fn sample_embeddings(
+///         &self,
+///         _: &str,
+///         _: ItemKind,
+///         _: &EmbeddingVersion,
+///         _: usize,
+///     ) -> Result<Vec<EmbeddedItem>> {
+///         Ok(vec![])
+///     }
+///     async fn rename_embeddings(&self, _: &[(String, ItemId)]) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+/// ```
+// the futures are only ever awaited by callers on their own task, so there's no need to
+// promise `Send`
+#[allow(async_fn_in_trait)]
+pub trait VectorDB {
+    async fn save_embedding(&self, item: &EmbeddedItem, version: &EmbeddingVersion) -> Result<()>;
+    /// upserts many PRs' or issues' embeddings in a single request
+    async fn save_embeddings(
+        &self,
+        items: &[EmbeddedItem],
+        version: &EmbeddingVersion,
+    ) -> Result<()>;
+    async fn remove_embedding(&self, id: &ItemId) -> Result<()>;
+    /// finds PRs or issues similar to `item` within the options' scope, only matching vectors
+    /// produced by the same `version`. Stored items with the same content hash are reported
+    /// as exact duplicates
+    async fn query(
+        &self,
+        item: &EmbeddedItem,
+        options: &QueryOptions,
+        version: &EmbeddingVersion,
+    ) -> Result<SimilarPRs>;
+    /// lists the id and version of every stored embedding
+    async fn list_embeddings(&self) -> Result<Vec<StoredEmbedding>>;
+    /// up to `limit` random embeddings of `repo_name`'s items of `kind` produced by `version`,
+    /// with their stored fingerprint, signature, paths and summary
+    async fn sample_embeddings(
+        &self,
+        repo_name: &str,
+        kind: ItemKind,
+        version: &EmbeddingVersion,
+        limit: usize,
+    ) -> Result<Vec<EmbeddedItem>>;
+    /// moves embeddings stored under the raw ids to their new ids, keeping vectors and metadata
+    async fn rename_embeddings(&self, renames: &[(String, ItemId)]) -> Result<()>;
 }
 
 /// sets HF HOME env if it doesn't exist
@@ -49,31 +309,7 @@ pub fn write_append<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io:
     }
     inner(path.as_ref(), contents.as_ref())
 }
-pub fn set_output(key: &str, value: &str) {
-    std::fs::write(env::var("GITHUB_OUTPUT").unwrap(), format!("{key}={value}")).unwrap();
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn uuid_encode_and_decode_pr_number() {
-        let repo_name = "cs50victor/pr_dedupe";
-        let pr_number = "2";
-
-        let uuid = uuid(repo_name, pr_number);
-
-        assert_eq!(uuid_to_pr_number(&uuid), pr_number);
-    }
-
-    #[test]
-    fn uuid_encode_and_decode_repo_name() {
-        let repo_name = "cs50victor/pr_dedupe";
-        let pr_number = "2";
-
-        let uuid = uuid(repo_name, pr_number);
-
-        assert_eq!(uuid_to_repo_name(&uuid), repo_name);
-    }
+/// appends an output to the step's `GITHUB_OUTPUT` file
+pub fn set_output(github_output: &str, key: &str, value: &str) -> io::Result<()> {
+    write_append(github_output, format!("{key}={value}\n"))
 }